@@ -3,6 +3,12 @@
 extern crate chrono;
 use self::chrono as chron;
 
+use json;
+use watch;
+
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+
 #[derive(Clone)]
 pub enum Color {
     /// An HTML color (#1234aa)
@@ -19,6 +25,23 @@ pub enum Color {
 }
 
 impl Color {
+    /// Parses a color name ("red", "orange", ...) or an HTML color ("#1234aa").
+    pub fn parse(s: &str) -> Option<Color> {
+        let valid_html = s.len() == 7 && s.starts_with('#') &&
+                         s[1..].chars().all(|c| c.is_digit(16));
+        match s.to_lowercase().as_str() {
+            "default" => Some(Color::Default),
+            "white" => Some(Color::White),
+            "red" => Some(Color::Red),
+            "green" => Some(Color::Green),
+            "blue" => Some(Color::Blue),
+            "black" => Some(Color::Black),
+            "orange" => Some(Color::Orange),
+            "purple" => Some(Color::Purple),
+            _ if valid_html => Some(Color::Arbitrary(String::from(s))),
+            _ => None,
+        }
+    }
     fn to_string(&self) -> String {
         match self.clone() {
             Color::Arbitrary(c) => c,
@@ -39,6 +62,7 @@ pub struct RenderResult {
     name: String,
     text: String,
     color: Color,
+    urgent: bool,
 }
 
 impl RenderResult {
@@ -47,15 +71,25 @@ impl RenderResult {
             name: String::new(),
             text: text,
             color: color,
+            urgent: false,
         }
     }
+    /// Marks the result as urgent; i3bar highlights urgent blocks.
+    pub fn with_urgent(mut self, urgent: bool) -> RenderResult {
+        self.urgent = urgent;
+        self
+    }
+    pub fn is_urgent(&self) -> bool {
+        self.urgent
+    }
     pub fn to_json(&self) -> String {
         let result = format!("{{\"name\": \
                               \"{name}\",\"color\":\"{color}\",\"markup\":\"none\",\"full_text\":\
-                              \"{text}\"}}",
-                             name = self.name,
+                              \"{text}\"{urgent}}}",
+                             name = json::escape(&self.name),
                              color = self.color.to_string(),
-                             text = self.text);
+                             text = json::escape(&self.text),
+                             urgent = if self.urgent { ",\"urgent\":true" } else { "" });
         result
     }
     pub fn get(&self) -> (String, Color) {
@@ -67,11 +101,26 @@ impl RenderResult {
 pub struct MetricState {
     /// Unix epoch in seconds. This is updated by the framework.
     pub last_called: i64,
+    wakeups: Option<Sender<Wakeup>>,
+    /// Event sources registered with watch_fd().
+    arms: Vec<Arc<watch::Arm>>,
 }
 
 impl MetricState {
     pub fn new() -> MetricState {
-        MetricState { last_called: 0 }
+        MetricState {
+            last_called: 0,
+            wakeups: None,
+            arms: Vec::new(),
+        }
+    }
+    /// Creates a state whose notifier() wakes up the render loop through wakeups.
+    pub fn with_wakeups(wakeups: Sender<Wakeup>) -> MetricState {
+        MetricState {
+            last_called: 0,
+            wakeups: Some(wakeups),
+            arms: Vec::new(),
+        }
     }
     /// Returns timestamp in epoch milliseconds.
     pub fn now() -> i64 {
@@ -79,6 +128,70 @@ impl MetricState {
         let t = chron::Local::now();
         1000 * t.timestamp() + (t.nanosecond() as i64 / 1000000)
     }
+    /// Returns a handle that metrics updated in the background (e.g. by a thread listening for
+    /// events) can use to request an immediate render.
+    pub fn notifier(&self) -> Option<Notifier> {
+        self.wakeups.as_ref().map(|tx| Notifier { wakeups: tx.clone() })
+    }
+    /// Renders the metric whenever fd (e.g. an inotify instance, a socket or a pipe) becomes
+    /// readable. The metric is expected to read the pending data in render(); fd must stay open
+    /// for as long as the metric exists. Returns false if there is no render loop to wake up.
+    pub fn watch_fd(&mut self, fd: i32) -> bool {
+        match self.notifier() {
+            None => false,
+            Some(notifier) => {
+                let arm = watch::Arm::new();
+                watch::watch_fd(fd, arm.clone(), notifier);
+                self.arms.push(arm);
+                true
+            }
+        }
+    }
+    /// Renders the metric once at the given time (epoch milliseconds), e.g. when a deadline
+    /// passes.
+    pub fn wake_at(&self, when: i64) {
+        if let Some(notifier) = self.notifier() {
+            watch::wake_at(when, MetricState::now(), notifier);
+        }
+    }
+    /// Lets the watched descriptors wake up the render loop again.
+    fn rearm(&self) {
+        for arm in self.arms.iter() {
+            arm.rearm();
+        }
+    }
+}
+
+/// Reasons for the render loop to wake up before the next interval boundary. Metrics are
+/// polled at every interval boundary; event-driven ones additionally wake up the loop through
+/// a Notifier (e.g. from a thread receiving events over a channel or socket), watch_fd() or
+/// wake_at().
+pub enum Wakeup {
+    Click(ClickEvent),
+    /// A metric has new data to show.
+    Update,
+}
+
+/// Requests an immediate render; see MetricState::notifier().
+#[derive(Clone)]
+pub struct Notifier {
+    wakeups: Sender<Wakeup>,
+}
+
+impl Notifier {
+    /// Returns false if the render loop has gone away.
+    pub fn notify(&self) -> bool {
+        self.wakeups.send(Wakeup::Update).is_ok()
+    }
+}
+
+/// A click on a block of the bar, as reported by i3bar.
+pub struct ClickEvent {
+    /// Name of the metric that was clicked.
+    pub name: String,
+    pub instance: Option<String>,
+    /// 1 = left, 2 = middle, 3 = right, 4/5 = scroll up/down.
+    pub button: u32,
 }
 
 pub trait Metric {
@@ -86,19 +199,31 @@ pub trait Metric {
     fn init(&mut self, _: &mut MetricState, _: Option<String>) {}
     /// Renders the metric.
     fn render(&mut self, st: &mut MetricState) -> RenderResult;
+    /// Called when rendering resumes after a pause. Metrics that compute rates should take a new
+    /// baseline here, so that the next render doesn't cover the whole pause.
+    fn reset(&mut self, _: &mut MetricState) {}
+    /// Called when the user clicked on the metric's block.
+    fn click(&mut self, _: &mut MetricState, _: &ClickEvent) {}
 }
 
 /// A metric that is active in the current run and updated for every cycle.
 pub struct ActiveMetric {
     name: String,
+    /// The argument the metric was initialized with; used to detect configuration changes.
+    arg: Option<String>,
     m: Box<Metric>,
     st: MetricState,
 }
 
 impl ActiveMetric {
-    pub fn new(name: String, metric: Box<Metric>, initial_state: MetricState) -> ActiveMetric {
+    pub fn new(name: String,
+               arg: Option<String>,
+               metric: Box<Metric>,
+               initial_state: MetricState)
+               -> ActiveMetric {
         ActiveMetric {
             name: name,
+            arg: arg,
             m: metric,
             st: initial_state,
         }
@@ -106,10 +231,40 @@ impl ActiveMetric {
     pub fn name(&self) -> &String {
         &self.name
     }
+    pub fn arg(&self) -> &Option<String> {
+        &self.arg
+    }
     pub fn render(&mut self) -> RenderResult {
         let mut result = self.m.render(&mut self.st);
         self.st.last_called = MetricState::now();
+        self.st.rearm();
         result.name = self.name.clone();
         result
     }
+    pub fn click(&mut self, ev: &ClickEvent) {
+        self.m.click(&mut self.st, ev);
+    }
+    pub fn reset(&mut self) {
+        self.m.reset(&mut self.st);
+        self.st.last_called = MetricState::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Color;
+
+    #[test]
+    fn test_parse_color() {
+        assert!(match Color::parse("Orange") {
+            Some(Color::Orange) => true,
+            _ => false,
+        });
+        assert!(match Color::parse("#88aaFF") {
+            Some(Color::Arbitrary(ref c)) => c == "#88aaFF",
+            _ => false,
+        });
+        assert!(Color::parse("#88aaFG").is_none());
+        assert!(Color::parse("mauve").is_none());
+    }
 }
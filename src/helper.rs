@@ -1,26 +1,24 @@
 #![allow(dead_code)]
 
+use std::env;
 use std::fs;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::iter::FromIterator;
 use std::iter::empty;
+use std::process::Command;
 use std::str::FromStr;
+use std::collections::BTreeMap;
 
+extern crate chrono;
 extern crate regex;
 use self::regex::Regex;
 
-/// Reads and returns a full procfs file.
-/// Example:
-///
-///     read_procfs_file("/net/dev")
-pub fn read_procfs_file(path: String) -> Option<String> {
+/// Reads and returns a full file.
+pub fn read_file(path: &str) -> Option<String> {
     let mut file;
     let size;
 
-    let mut fullpath = String::from("/proc/");
-    fullpath.push_str(&path);
-
-    match fs::OpenOptions::new().read(true).open(fullpath) {
+    match fs::OpenOptions::new().read(true).open(path) {
         Err(_) => return None,
         Ok(f) => file = f,
     }
@@ -37,6 +35,58 @@ pub fn read_procfs_file(path: String) -> Option<String> {
     }
 }
 
+/// Reads and returns a full procfs file.
+/// Example:
+///
+///     read_procfs_file("/net/dev")
+pub fn read_procfs_file(path: String) -> Option<String> {
+    let mut fullpath = String::from("/proc/");
+    fullpath.push_str(&path);
+    read_file(&fullpath)
+}
+
+/// Reads and returns a full sysfs file, without trailing whitespace.
+/// Example:
+///
+///     read_sysfs_file("/class/net/eth0/operstate")
+pub fn read_sysfs_file(path: String) -> Option<String> {
+    let mut fullpath = String::from("/sys/");
+    fullpath.push_str(&path);
+    read_file(&fullpath).map(|s| String::from(s.trim_end()))
+}
+
+/// Writes a file by writing a temporary file first and renaming it to path; this way, readers
+/// never see a partially written file. Returns false on error.
+pub fn write_file(path: &str, content: &str) -> bool {
+    let tmp = format!("{}.tmp", path);
+    let written = fs::File::create(&tmp).and_then(|mut f| f.write_all(content.as_bytes()));
+
+    written.and_then(|_| fs::rename(&tmp, path)).is_ok()
+}
+
+/// Returns the path of a file in myi3stat's state directory ($XDG_STATE_HOME/myi3stat or
+/// ~/.local/state/myi3stat), which is created if necessary.
+pub fn state_file_path(name: &str) -> Option<String> {
+    let dir = match (env::var("XDG_STATE_HOME"), env::var("HOME")) {
+        (Ok(ref state), _) if !state.is_empty() => format!("{}/myi3stat", state),
+        (_, Ok(home)) => format!("{}/.local/state/myi3stat", home),
+        _ => return None,
+    };
+
+    match fs::create_dir_all(&dir) {
+        Err(_) => None,
+        Ok(_) => Some(format!("{}/{}", dir, name)),
+    }
+}
+
+/// Expands a leading "~/" to $HOME.
+pub fn expand_home(path: &str) -> String {
+    match (path.starts_with("~/"), env::var("HOME")) {
+        (true, Ok(home)) => format!("{}{}", home, &path[1..]),
+        _ => String::from(path),
+    }
+}
+
 /// Splits the result of read_procfs_file() into lines.
 pub fn get_procfs_file_lines(path: String) -> Option<Vec<String>> {
     match read_procfs_file(path) {
@@ -45,6 +95,53 @@ pub fn get_procfs_file_lines(path: String) -> Option<Vec<String>> {
     }
 }
 
+/// Returns the number of CPU cores, as counted in /proc/stat.
+pub fn number_of_cores() -> i32 {
+    let individual_cpu_regex = Regex::new(r"^cpu(\d+)\s+").unwrap();
+
+    match get_procfs_file_lines(String::from("/stat")) {
+        None => 1,
+        Some(lns) => {
+            let mut n = 0;
+            for line in lns {
+                if individual_cpu_regex.is_match(&line) {
+                    n += 1;
+                } else if n > 0 {
+                    break;
+                }
+            }
+            if n > 0 { n } else { 1 }
+        }
+    }
+}
+
+/// Divides value by factor until the result is less than factor or the last unit is reached.
+/// Returns the scaled value and its unit; units must not be empty.
+/// Example:
+///
+///     scale_to_unit(3400000., 1000., &["kHz", "MHz", "GHz"]) == (3.4, "GHz")
+pub fn scale_to_unit<'a>(value: f64, factor: f64, units: &[&'a str]) -> (f64, &'a str) {
+    debug_assert!(!units.is_empty());
+    let mut u = 0;
+    let mut f = value;
+
+    while f / factor >= 1. && u < units.len() - 1 {
+        f = f / factor;
+        u += 1;
+    }
+    (f, units[u])
+}
+
+/// Formats an amount of KiB: "812K", "1.4G".
+pub fn format_kib(kib: u64) -> String {
+    let (f, unit) = scale_to_unit(kib as f64, 1024., &["K", "M", "G", "T"]);
+    if unit == "K" {
+        format!("{:.0}{}", f, unit)
+    } else {
+        format!("{:.1}{}", f, unit)
+    }
+}
+
 /// Splits a string at commas (',') and returns the list of the elements separated.
 pub fn commaseparated_to_vec(s: String) -> Vec<String> {
     s.split(",").map(String::from).collect()
@@ -70,6 +167,71 @@ pub fn extract_from_str<T: FromStr + Clone, C: FromIterator<T>>(s: &String,
     }
 }
 
+/// Runs a command and returns its standard output if it exited successfully.
+/// Example:
+///
+///     run_command("iw", &["dev", "wlan0", "link"])
+pub fn run_command(cmd: &str, args: &[&str]) -> Option<String> {
+    match Command::new(cmd).args(args).output() {
+        Err(_) => None,
+        Ok(ref out) if !out.status.success() => None,
+        Ok(out) => Some(String::from_utf8_lossy(&out.stdout).into_owned()),
+    }
+}
+
+/// Substitutes placeholders of the form %x in fmt by the value stored for 'x'. "%%" yields a
+/// literal '%'; unknown placeholders are left as they are.
+pub fn format_template(fmt: &str, values: &BTreeMap<char, String>) -> String {
+    let mut result = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            None => result.push('%'),
+            Some('%') => result.push('%'),
+            Some(p) => {
+                match values.get(&p) {
+                    Some(v) => result.push_str(v),
+                    None => {
+                        result.push('%');
+                        result.push(p);
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Shortens s to at most max characters, ending in an ellipsis if it was cut. 0 means no limit.
+pub fn truncate(s: &str, max: usize) -> String {
+    if max == 0 || s.chars().count() <= max {
+        return String::from(s);
+    }
+    let mut short: String = s.chars().take(max - 1).collect();
+    short.push('…');
+    short
+}
+
+/// Checks whether fmt is a valid strftime-like format string as understood by chrono;
+/// formatting with an invalid one would panic. Returns a message describing the problem.
+pub fn check_strftime(fmt: &str) -> Result<(), String> {
+    use self::chrono::format::{Item, StrftimeItems};
+
+    for item in StrftimeItems::new(fmt) {
+        if let Item::Error = item {
+            return Err(format!("invalid format string '{}' (see strftime(3) for valid \
+                                specifiers)",
+                               fmt));
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     extern crate regex;
@@ -87,4 +249,32 @@ mod tests {
         assert_eq!(result[1], 456);
         assert_eq!(result.len(), 2);
     }
+
+    #[test]
+    fn test_format_template() {
+        use std::collections::BTreeMap;
+
+        let mut values = BTreeMap::new();
+        values.insert('s', String::from("home"));
+        values.insert('q', String::from("72"));
+
+        assert_eq!(format_template("%s %q%% %x", &values), "home 72% %x");
+        assert_eq!(format_template("%", &values), "%");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("Mozilla Firefox", 8), "Mozilla…");
+        assert_eq!(truncate("äöüäöü", 4), "äöü…");
+        assert_eq!(truncate("unlimited", 0), "unlimited");
+    }
+
+    #[test]
+    fn test_format_kib() {
+        assert_eq!(format_kib(812), "812K");
+        assert_eq!(format_kib(204800), "200.0M");
+        assert_eq!(format_kib(1468006), "1.4G");
+        assert_eq!(format_kib(1048576), "1.0G");
+    }
 }
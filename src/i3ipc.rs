@@ -0,0 +1,179 @@
+//! A client for the IPC interface of i3 and sway. Messages are framed as "i3-ipc", the payload
+//! length and the message type (both 32 bit, native byte order), followed by a JSON payload.
+
+#![allow(dead_code)]
+
+use helper::run_command;
+use json;
+use json::Json;
+
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+const MAGIC: &'static [u8] = b"i3-ipc";
+
+pub const RUN_COMMAND: u32 = 0;
+pub const GET_WORKSPACES: u32 = 1;
+pub const SUBSCRIBE: u32 = 2;
+pub const GET_TREE: u32 = 4;
+pub const GET_BINDING_STATE: u32 = 12;
+/// sway only.
+pub const GET_INPUTS: u32 = 100;
+
+/// Event types have the highest bit set.
+pub const EVENT_BIT: u32 = 0x80000000;
+pub const EVENT_WORKSPACE: u32 = EVENT_BIT | 0;
+pub const EVENT_MODE: u32 = EVENT_BIT | 2;
+pub const EVENT_WINDOW: u32 = EVENT_BIT | 3;
+pub const EVENT_SHUTDOWN: u32 = EVENT_BIT | 6;
+/// sway only.
+pub const EVENT_INPUT: u32 = EVENT_BIT | 21;
+
+/// Returns the path of the IPC socket: $I3SOCK, $SWAYSOCK or what i3 reports.
+pub fn socket_path() -> Option<String> {
+    for var in ["I3SOCK", "SWAYSOCK"].iter() {
+        if let Ok(path) = env::var(var) {
+            if !path.is_empty() {
+                return Some(path);
+            }
+        }
+    }
+    run_command("i3", &["--get-socketpath"])
+        .map(|p| String::from(p.trim()))
+        .filter(|p| !p.is_empty())
+}
+
+pub struct Connection {
+    stream: UnixStream,
+}
+
+impl Connection {
+    pub fn connect(path: &str) -> Result<Connection, String> {
+        UnixStream::connect(path)
+            .map(|s| Connection { stream: s })
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn send(&mut self, msg_type: u32, payload: &str) -> Result<(), String> {
+        let mut msg = Vec::with_capacity(MAGIC.len() + 8 + payload.len());
+        msg.extend_from_slice(MAGIC);
+        msg.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        msg.extend_from_slice(&msg_type.to_ne_bytes());
+        msg.extend_from_slice(payload.as_bytes());
+        self.stream.write_all(&msg).map_err(|e| e.to_string())
+    }
+
+    /// Reads the next message; returns its type and payload.
+    pub fn recv(&mut self) -> Result<(u32, Json), String> {
+        let mut header = [0u8; 14];
+        self.stream.read_exact(&mut header).map_err(|e| e.to_string())?;
+        if &header[0..6] != MAGIC {
+            return Err(String::from("invalid message header"));
+        }
+        let mut len = [0u8; 4];
+        let mut msg_type = [0u8; 4];
+        len.copy_from_slice(&header[6..10]);
+        msg_type.copy_from_slice(&header[10..14]);
+
+        let mut payload = vec![0u8; u32::from_ne_bytes(len) as usize];
+        self.stream.read_exact(&mut payload).map_err(|e| e.to_string())?;
+        let payload = json::parse(&String::from_utf8_lossy(&payload))?;
+        Ok((u32::from_ne_bytes(msg_type), payload))
+    }
+
+    /// Sends a request and returns the reply. Must not be used on a subscribed connection, as
+    /// events may arrive before the reply.
+    pub fn request(&mut self, msg_type: u32, payload: &str) -> Result<Json, String> {
+        self.send(msg_type, payload)?;
+        match self.recv()? {
+            (t, reply) if t == msg_type => Ok(reply),
+            (t, _) => Err(format!("unexpected reply type {}", t)),
+        }
+    }
+
+    /// Subscribes to events, e.g. ["window", "mode"]. Afterwards, only recv() should be used.
+    pub fn subscribe(&mut self, events: &[&str]) -> Result<(), String> {
+        let names: Vec<String> = events.iter().map(|e| format!("\"{}\"", e)).collect();
+        let reply = self.request(SUBSCRIBE, &format!("[{}]", names.join(",")))?;
+
+        match reply.get("success").and_then(Json::as_bool) {
+            Some(true) => Ok(()),
+            _ => Err(String::from("subscription failed")),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+    use std::thread;
+
+    /// Writes a message in i3's framing.
+    pub fn write_message(stream: &mut UnixStream, msg_type: u32, payload: &str) {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(MAGIC);
+        msg.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        msg.extend_from_slice(&msg_type.to_ne_bytes());
+        msg.extend_from_slice(payload.as_bytes());
+        stream.write_all(&msg).unwrap();
+    }
+
+    /// Reads a message in i3's framing; returns type and payload.
+    pub fn read_message(stream: &mut UnixStream) -> Option<(u32, String)> {
+        let mut header = [0u8; 14];
+        stream.read_exact(&mut header).ok()?;
+        let mut len = [0u8; 4];
+        let mut msg_type = [0u8; 4];
+        len.copy_from_slice(&header[6..10]);
+        msg_type.copy_from_slice(&header[10..14]);
+        let mut payload = vec![0u8; u32::from_ne_bytes(len) as usize];
+        stream.read_exact(&mut payload).ok()?;
+        Some((u32::from_ne_bytes(msg_type), String::from_utf8(payload).unwrap()))
+    }
+
+    /// Binds a fresh socket for a stand-in i3.
+    pub fn listen(name: &str) -> (UnixListener, String) {
+        let path = format!("{}/myi3stat-test-{}-{}.sock",
+                           env::temp_dir().display(),
+                           process::id(),
+                           name);
+        let _ = fs::remove_file(&path);
+        (UnixListener::bind(&path).unwrap(), path)
+    }
+
+    #[test]
+    fn test_request_and_events() {
+        let (listener, path) = listen("ipc");
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            assert_eq!(read_message(&mut conn),
+                       Some((GET_BINDING_STATE, String::new())));
+            write_message(&mut conn, GET_BINDING_STATE, r#"{"name":"default"}"#);
+
+            assert_eq!(read_message(&mut conn),
+                       Some((SUBSCRIBE, String::from(r#"["mode"]"#))));
+            write_message(&mut conn, SUBSCRIBE, r#"{"success":true}"#);
+            write_message(&mut conn, EVENT_MODE, r#"{"change":"resize"}"#);
+        });
+
+        let mut conn = Connection::connect(&path).unwrap();
+        let state = conn.request(GET_BINDING_STATE, "").unwrap();
+        assert_eq!(state.get("name").and_then(Json::as_str), Some("default"));
+
+        conn.subscribe(&["mode"]).unwrap();
+        let (t, ev) = conn.recv().unwrap();
+        assert_eq!(t, EVENT_MODE);
+        assert_eq!(ev.get("change").and_then(Json::as_str), Some("resize"));
+
+        server.join().unwrap();
+        // The stand-in has closed the connection.
+        assert!(conn.recv().is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
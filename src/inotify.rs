@@ -0,0 +1,129 @@
+//! A minimal wrapper around Linux' inotify(7) API.
+
+#![allow(dead_code)]
+
+extern crate libc;
+
+use std::ffi::CString;
+use std::mem;
+
+pub const IN_MODIFY: u32 = 0x00000002;
+pub const IN_ATTRIB: u32 = 0x00000004;
+pub const IN_CLOSE_WRITE: u32 = 0x00000008;
+pub const IN_MOVED_FROM: u32 = 0x00000040;
+pub const IN_MOVED_TO: u32 = 0x00000080;
+pub const IN_CREATE: u32 = 0x00000100;
+pub const IN_DELETE: u32 = 0x00000200;
+pub const IN_DELETE_SELF: u32 = 0x00000400;
+pub const IN_MOVE_SELF: u32 = 0x00000800;
+/// Events were dropped because the queue overflowed.
+pub const IN_Q_OVERFLOW: u32 = 0x00004000;
+/// The watch was removed, e.g. because the watched file was deleted.
+pub const IN_IGNORED: u32 = 0x00008000;
+
+const IN_NONBLOCK: libc::c_int = 0o4000;
+const IN_CLOEXEC: libc::c_int = 0o2000000;
+
+extern "C" {
+    fn inotify_init1(flags: libc::c_int) -> libc::c_int;
+    fn inotify_add_watch(fd: libc::c_int, path: *const libc::c_char, mask: u32) -> libc::c_int;
+    fn inotify_rm_watch(fd: libc::c_int, wd: libc::c_int) -> libc::c_int;
+}
+
+/// Mirrors `struct inotify_event` (without the trailing name).
+#[repr(C)]
+struct RawEvent {
+    wd: libc::c_int,
+    mask: u32,
+    cookie: u32,
+    len: u32,
+}
+
+/// An event read from an inotify descriptor.
+pub struct Event {
+    pub wd: i32,
+    pub mask: u32,
+    /// File name relative to the watched directory; empty for events on the watched file itself.
+    pub name: String,
+}
+
+/// A non-blocking inotify instance.
+pub struct Inotify {
+    fd: libc::c_int,
+}
+
+impl Inotify {
+    pub fn new() -> Option<Inotify> {
+        let fd = unsafe { inotify_init1(IN_NONBLOCK | IN_CLOEXEC) };
+        if fd < 0 { None } else { Some(Inotify { fd: fd }) }
+    }
+
+    /// Returns the underlying file descriptor, e.g. for use with poll(2).
+    pub fn fd(&self) -> i32 {
+        self.fd
+    }
+
+    /// Adds a watch for path; returns the watch descriptor.
+    pub fn add_watch(&mut self, path: &str, mask: u32) -> Option<i32> {
+        let cpath = match CString::new(path) {
+            Err(_) => return None,
+            Ok(p) => p,
+        };
+        let wd = unsafe { inotify_add_watch(self.fd, cpath.as_ptr(), mask) };
+        if wd < 0 { None } else { Some(wd) }
+    }
+
+    pub fn rm_watch(&mut self, wd: i32) {
+        unsafe {
+            inotify_rm_watch(self.fd, wd);
+        }
+    }
+
+    /// Returns all events that are currently pending; never blocks.
+    pub fn read_events(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut buf = [0u8; 4096];
+        let header = mem::size_of::<RawEvent>();
+
+        loop {
+            let n = unsafe {
+                libc::read(self.fd,
+                           buf.as_mut_ptr() as *mut libc::c_void,
+                           buf.len() as libc::size_t)
+            };
+            if n <= 0 {
+                break;
+            }
+
+            let n = n as usize;
+            let mut off = 0;
+            while off + header <= n {
+                let raw: RawEvent = unsafe {
+                    ::std::ptr::read_unaligned(buf[off..].as_ptr() as *const RawEvent)
+                };
+                let name_start = off + header;
+                let name_end = name_start + raw.len as usize;
+                let name = buf[name_start..name_end.min(n)]
+                               .split(|b| *b == 0)
+                               .next()
+                               .map(|s| String::from_utf8_lossy(s).into_owned())
+                               .unwrap_or(String::new());
+                events.push(Event {
+                    wd: raw.wd,
+                    mask: raw.mask,
+                    name: name,
+                });
+                off = name_end;
+            }
+        }
+        events
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
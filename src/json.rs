@@ -0,0 +1,252 @@
+//! A small JSON parser, sufficient for the messages exchanged with i3bar and similar programs.
+
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::str::Chars;
+use std::iter::Peekable;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    /// Returns the member key of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref m) => m.get(key),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match *self {
+            Json::Array(ref a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+/// Escapes a string for inclusion in a JSON string literal.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Parses a complete JSON document.
+pub fn parse(s: &str) -> Result<Json, String> {
+    let mut p = Parser { chars: s.chars().peekable() };
+    let value = p.parse_value()?;
+    p.skip_whitespace();
+    match p.chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("unexpected '{}' after value", c)),
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().map_or(false, |c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        for expected in word.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(format!("expected '{}'", word));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek().cloned() {
+            None => Err(String::from("unexpected end of input")),
+            Some('{') => self.parse_object(),
+            Some('[') => self.parse_array(),
+            Some('"') => self.parse_string().map(Json::String),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some(c) if c == '-' || c.is_digit(10) => self.parse_number(),
+            Some(c) => Err(format!("unexpected '{}'", c)),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Json, String> {
+        let mut num = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_digit(10) || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' {
+                num.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        num.parse().map(Json::Number).map_err(|_| format!("invalid number '{}'", num))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let mut n = 0;
+        for _ in 0..4 {
+            let d = self.chars.next().and_then(|c| c.to_digit(16));
+            match d {
+                None => return Err(String::from("invalid \\u escape")),
+                Some(d) => n = n * 16 + d,
+            }
+        }
+        Ok(n)
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        let mut s = String::new();
+        self.chars.next(); // opening quote
+
+        loop {
+            match self.chars.next() {
+                None => return Err(String::from("unterminated string")),
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    match self.chars.next() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('r') => s.push('\r'),
+                        Some('b') => s.push('\u{8}'),
+                        Some('f') => s.push('\u{c}'),
+                        Some('u') => {
+                            let mut code = self.parse_hex4()?;
+                            // UTF-16 surrogate pair
+                            if code >= 0xd800 && code < 0xdc00 {
+                                self.expect("\\u")?;
+                                let low = self.parse_hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            s.push(::std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                        }
+                        Some(c) => s.push(c),
+                        None => return Err(String::from("unterminated string")),
+                    }
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, String> {
+        let mut items = Vec::new();
+        self.chars.next(); // [
+
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(items)),
+                _ => return Err(String::from("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, String> {
+        let mut members = BTreeMap::new();
+        self.chars.next(); // {
+
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'"') {
+                return Err(String::from("expected member name"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(":")?;
+            let value = self.parse_value()?;
+            members.insert(key, value);
+
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(members)),
+                _ => return Err(String::from("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let doc = parse(r#"{"name": "clock", "button": 1, "x": -2.5e1, "mods": ["Shift"],
+                            "ok": true, "none": null, "s": "a\"bä😀"}"#)
+                      .unwrap();
+
+        assert_eq!(doc.get("name").and_then(Json::as_str), Some("clock"));
+        assert_eq!(doc.get("button").and_then(Json::as_f64), Some(1.));
+        assert_eq!(doc.get("x").and_then(Json::as_f64), Some(-25.));
+        assert_eq!(doc.get("mods").and_then(Json::as_array).map(|a| a.len()), Some(1));
+        assert_eq!(doc.get("ok").and_then(Json::as_bool), Some(true));
+        assert_eq!(doc.get("none"), Some(&Json::Null));
+        assert_eq!(doc.get("s").and_then(Json::as_str), Some("a\"b\u{e4}\u{1f600}"));
+
+        assert!(parse("{\"a\": }").is_err());
+        assert!(parse("[1, 2").is_err());
+        assert_eq!(parse(" [] "), Ok(Json::Array(vec![])));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\n"), "a\\\"b\\\\c\\n");
+    }
+}
@@ -1,30 +1,54 @@
 mod framework;
 mod helper;
+mod i3ipc;
+mod inotify;
+mod json;
 mod metrics;
+mod procs;
 mod render;
+mod schedule;
+mod signals;
+mod tz;
+mod watch;
 
 use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
 use std::process;
 use std::env;
+use std::sync::mpsc::{channel, Sender};
 
 extern crate getopts;
 use getopts::Options;
 
 use framework::*;
+use inotify::Inotify;
 use render::*;
 
+/// Creates a new, uninitialized instance of a metric.
+type MetricFactory = fn() -> Box<Metric>;
+
 /// Represents a/the set of metrics available for display.
 struct Config {
-    metrics: BTreeMap<String, Box<Metric>>,
+    metrics: BTreeMap<String, MetricFactory>,
     renderers: BTreeMap<String, Box<Renderer>>,
     opts: Options,
+
+    /// Command line arguments; kept in order to re-evaluate them on reload.
+    args: Vec<String>,
+    interval: i32,
+    /// Watches the directory of the configuration file (if any), and the file's name.
+    watcher: Option<(Inotify, String)>,
+    /// Given to metrics so that they can wake up the render loop.
+    wakeups: Sender<Wakeup>,
 }
 
 
 /// Set of all metrics. Used to register metrics and select the active ones based on the user's
 /// selection.
 impl Config {
-    fn new() -> Config {
+    fn new(wakeups: Sender<Wakeup>) -> Config {
         let mut options = Options::new();
         options.optopt("",
                        "ordering",
@@ -33,27 +57,38 @@ impl Config {
                        "METRIC1,METRIC2,METRIC3");
         options.optopt("",
                        "interval",
-                       "Interval in milliseconds between individual render cycles. Default: 1000",
+                       "Interval in milliseconds between individual render cycles. Cycles are aligned \
+                        to multiples of the interval in local time. Default: 1000",
                        "SECONDS");
         options.optopt("",
                        "renderer",
                        "Which renderer to use. Currently available: i3status,plain",
                        "i3status");
+        options.optopt("",
+                       "config",
+                       "Read further options from FILE, one per line (e.g. '--clock %H:%M'). The \
+                        file is re-read on SIGHUP and whenever it changes",
+                       "FILE");
         options.optflag("h", "help", "Print a help text");
 
         Config {
             metrics: BTreeMap::new(),
             renderers: BTreeMap::new(),
             opts: options,
+            args: Vec::new(),
+            interval: 1000,
+            watcher: None,
+            wakeups: wakeups,
         }
     }
 
     /// Register a metric under the given name.
     /// desc and example are for the purpose of documenting the command line option that is added.
-    fn register_metric(&mut self, name: &str, desc: &str, example: &str, metric: Box<Metric>) {
+    /// factory is called every time the metric is activated.
+    fn register_metric(&mut self, name: &str, desc: &str, example: &str, factory: MetricFactory) {
         if !self.metrics.contains_key(&String::from(name)) {
             self.opts.optflagopt("", name, desc, example);
-            self.metrics.insert(String::from(name), metric);
+            self.metrics.insert(String::from(name), factory);
         }
     }
 
@@ -67,11 +102,64 @@ impl Config {
         print!("{}", self.opts.usage("Usage: myi3stat [options]"));
     }
 
+    /// Reads options from a configuration file. Every line that is not empty and doesn't start
+    /// with '#' contains an option name, optionally followed by whitespace and the value:
+    ///
+    ///     --interval 2000
+    ///     --clock %a %d.%m. %H:%M
+    fn read_config_file(path: &str) -> Result<Vec<String>, String> {
+        let mut content = String::new();
+
+        if let Err(e) = fs::File::open(path).and_then(|mut f| f.read_to_string(&mut content)) {
+            return Err(format!("{}: {}", path, e));
+        }
+
+        let mut args = Vec::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+            let (name, value) = match line.find(char::is_whitespace) {
+                None => (line, None),
+                Some(i) => (&line[..i], Some(line[i..].trim())),
+            };
+            let mut arg = String::from(if name.starts_with("-") { "" } else { "--" });
+            arg.push_str(name);
+            if let Some(v) = value {
+                arg.push_str("=");
+                arg.push_str(v);
+            }
+            args.push(arg);
+        }
+        Ok(args)
+    }
+
+    /// Parses the command line arguments plus the contents of the configuration file, if one was
+    /// given. Options given on the command line override those in the file.
+    fn parse(&self, args: &[String]) -> Result<getopts::Matches, String> {
+        let matches = self.opts.parse(args).map_err(|e| e.to_string())?;
+
+        match matches.opt_str("config") {
+            None => Ok(matches),
+            Some(path) => {
+                let mut all_args = args.to_vec();
+                for arg in Config::read_config_file(&path)? {
+                    let name = arg.trim_start_matches('-').split('=').next().unwrap_or("");
+                    if !matches.opts_present(&[String::from(name)]) {
+                        all_args.push(arg);
+                    }
+                }
+                self.opts.parse(&all_args).map_err(|e| e.to_string())
+            }
+        }
+    }
+
     fn parse_args(&mut self, args: &[String]) -> getopts::Matches {
-        let matches = self.opts.parse(args);
+        let matches = self.parse(args);
 
         match matches {
-            Err(_) => {
+            Err(e) => {
+                println!("{}", e);
                 self.print_help();
                 process::exit(1)
             }
@@ -98,20 +186,29 @@ impl Config {
         ordmap
     }
 
-    /// Returns the selected renderer; the list of selected metrics; and the chosen interval in
-    /// milliseconds.
-    fn evaluate(mut self, args: &[String]) -> (Box<Renderer>, Vec<ActiveMetric>, i32) {
-        use std::str::FromStr;
-
-        let matches = self.parse_args(args);
+    /// Instantiates and initializes the metrics selected in matches, ordered as requested by the
+    /// user. Metrics from old that were configured with the same argument are re-used.
+    fn select_metrics(&self,
+                      matches: &getopts::Matches,
+                      mut old: Vec<ActiveMetric>)
+                      -> Vec<ActiveMetric> {
         let mut metrics = Vec::new();
 
         // Look for every defined metric if the user wants to have it displayed.
-        for (metric_name, mut metric) in self.metrics.into_iter() {
-            if matches.opt_present(&metric_name) {
-                let mut st = MetricState::new();
-                metric.init(&mut st, matches.opt_str(&metric_name));
-                metrics.push(ActiveMetric::new(String::from(metric_name), metric, st));
+        for (metric_name, factory) in self.metrics.iter() {
+            if !matches.opt_present(metric_name) {
+                continue;
+            }
+            let arg = matches.opt_str(metric_name);
+
+            match old.iter().position(|m| m.name() == metric_name && *m.arg() == arg) {
+                Some(i) => metrics.push(old.swap_remove(i)),
+                None => {
+                    let mut metric = factory();
+                    let mut st = MetricState::with_wakeups(self.wakeups.clone());
+                    metric.init(&mut st, arg.clone());
+                    metrics.push(ActiveMetric::new(metric_name.clone(), arg, metric, st));
+                }
             }
         }
 
@@ -125,10 +222,50 @@ impl Config {
                 (_, _) => a.name().cmp(b.name()),
             }
         });
+        metrics
+    }
+
+    fn parse_interval(matches: &getopts::Matches) -> i32 {
+        use std::str::FromStr;
+        i32::from_str(&matches.opt_str("interval").unwrap_or(String::from("1000"))).unwrap_or(1000)
+    }
+
+    /// Sets up an inotify watch on the directory containing the configuration file. The directory
+    /// is watched instead of the file because editors usually replace files instead of writing
+    /// to them.
+    fn watch_config(&mut self, path: &str) {
+        let path = Path::new(path);
+        let dir = match path.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d.to_string_lossy().into_owned(),
+            _ => String::from("."),
+        };
+        let file = match path.file_name() {
+            None => return,
+            Some(f) => f.to_string_lossy().into_owned(),
+        };
+
+        if let Some(mut inotify) = Inotify::new() {
+            let mask = inotify::IN_CLOSE_WRITE | inotify::IN_MOVED_TO | inotify::IN_CREATE;
+            if inotify.add_watch(&dir, mask).is_some() {
+                self.watcher = Some((inotify, file));
+            }
+        }
+    }
+
+    /// Returns the selected renderer; the list of selected metrics; and the chosen interval in
+    /// milliseconds.
+    fn evaluate(&mut self, args: &[String]) -> (Box<Renderer>, Vec<ActiveMetric>, i32) {
+        let matches = self.parse_args(args);
+        self.args = args.to_vec();
+
+        if let Some(path) = matches.opt_str("config") {
+            self.watch_config(&path);
+        }
+
+        let metrics = self.select_metrics(&matches, Vec::new());
 
         // Select and set up renderer
-        let interval = i32::from_str(&matches.opt_str("interval").unwrap_or(String::from("1000")))
-                           .unwrap_or(1000);
+        self.interval = Config::parse_interval(&matches);
         let renderer_name = matches.opt_str("renderer").unwrap_or(String::from("i3status"));
 
 
@@ -138,36 +275,194 @@ impl Config {
 
         let renderer = self.renderers.remove(&renderer_name).unwrap();
 
-        (renderer, metrics, interval)
+        (renderer, metrics, self.interval)
+    }
+}
+
+impl Reload for Config {
+    fn pending(&mut self) -> bool {
+        let config_changed = match self.watcher {
+            None => false,
+            Some((ref mut inotify, ref file)) => {
+                inotify.read_events().iter().any(|ev| ev.name == *file)
+            }
+        };
+        signals::reload_requested() || config_changed
+    }
+
+    fn reload(&mut self, old: Vec<ActiveMetric>) -> (Vec<ActiveMetric>, i32) {
+        match self.parse(&self.args) {
+            Err(e) => {
+                eprintln!("myi3stat: keeping old configuration: {}", e);
+                (old, self.interval)
+            }
+            Ok(matches) => {
+                let metrics = self.select_metrics(&matches, old);
+                self.interval = Config::parse_interval(&matches);
+                (metrics, self.interval)
+            }
+        }
     }
 }
 
 fn register_metrics(registry: &mut Config) {
+    use metrics::agenda;
+    use metrics::backlight;
+    use metrics::containers;
     use metrics::cpu_load;
+    use metrics::cpufreq;
+    use metrics::i3;
+    use metrics::ipaddr;
+    use metrics::kbd;
     use metrics::load;
+    use metrics::mail;
+    use metrics::mpd;
     use metrics::net;
+    use metrics::proc_watch;
+    use metrics::psi;
+    use metrics::systemd;
     use metrics::time;
+    use metrics::timer;
+    use metrics::top;
+    use metrics::uptime;
+    use metrics::volume;
+    use metrics::wifi;
 
     // List of codes: https://lifthrasiir.github.io/rust-chrono/chrono/format/strftime/index.html
     registry.register_metric("clock",
-                             "A timestamp clock. Uses format codes like date(1)",
-                             "%H:%M",
-                             time::clock_metric());
+                             "A timestamp clock. Uses format codes like date(1), optionally \
+                              followed by ';' and time zones, and ';' and a format shown after \
+                              clicking",
+                             "%H:%M;UTC,America/New_York;%d.%m.",
+                             time::clock_metric);
     registry.register_metric("netif",
-                             "Shows total received/transmitted bytes for network interaces",
-                             "eth0,lo",
-                             net::make_net_metric());
+                             "Shows received/transmitted bytes per second for network interfaces. \
+                              Takes globs (eth*), regexes (/^wl.*/) or 'auto' for all interfaces \
+                              that are up. Options: 'total' shows the sum, 'session' the bytes \
+                              transferred since startup, 'daily' today's traffic",
+                             "auto|eth0,wl* [total] [session] [daily]",
+                             net::make_net_metric);
     registry.register_metric("load",
-                             "Shows the last three load averages over the last (1, 5, 15) \
-                              minutes.",
-                             "",
-                             load::make_load_metric());
+                             "Shows the load averages over the last (1, 5, 15) minutes. Options: \
+                              'norm' divides by the number of cores, 'tasks' shows runnable/total \
+                              tasks, 'pid' the last PID",
+                             "1,5,15 norm tasks pid",
+                             load::make_load_metric);
     registry.register_metric("cpu_load",
                              "Shows the cpu load in percent over the last measure interval. abs \
                               means: 4 core seconds = 400%; rel means (on a quadcore): 4 core \
                               seconds = 100%",
                              "abs|rel",
-                             cpu_load::make_cpu_load_metric());
+                             cpu_load::make_cpu_load_metric);
+    registry.register_metric("wifi",
+                             "Shows SSID, link quality and bitrate of a wireless interface (first \
+                              one if omitted). Format: %i interface, %s SSID, %q quality in \
+                              percent, %l signal level in dBm, %b bitrate",
+                             "wlan0 %s %q%% %b",
+                             wifi::make_wifi_metric);
+    registry.register_metric("ipaddr",
+                             "Shows the IP addresses of network interfaces; 'default' is the \
+                              interface holding the default route. Add 'nolinklocal' to hide \
+                              link-local addresses",
+                             "default,eth0 nolinklocal",
+                             ipaddr::make_ipaddr_metric);
+    registry.register_metric("psi",
+                             "Shows pressure stall information: resources (cpu,memory,io), fields \
+                              (some|full + 10|60|300) and options cgroup=DIR, warn=N, crit=N",
+                             "cpu,memory,io some10,full60 warn=10 crit=40",
+                             psi::make_psi_metric);
+    registry.register_metric("cpufreq",
+                             "Shows the CPU frequency: average, min/avg/max (range) or per core. \
+                              'gov' adds the scaling governor, 'epp' the energy performance \
+                              preference",
+                             "avg|range|percore [gov] [epp]",
+                             cpufreq::make_cpufreq_metric);
+    registry.register_metric("uptime",
+                             "Shows the uptime. Format: %d days, %h hours, %m minutes, %s seconds, \
+                              %H/%M total hours/minutes, %i idle percentage, %b boot time (format \
+                              after ';' like clock)",
+                             "%dd %hh;%Y-%m-%d %H:%M",
+                             uptime::make_uptime_metric);
+    registry.register_metric("timer",
+                             "A countdown to a local date and time, a stopwatch or a pomodoro \
+                              timer (work, break and long break in minutes). Click to start/stop, \
+                              right-click to reset; the state is kept across restarts",
+                             "countdown 2017-12-24 18:00 xmas|stopwatch|pomodoro 25 5 15",
+                             timer::make_timer_metric);
+    registry.register_metric("agenda",
+                             "Shows the next event from .ics files or vdir directories, urgent \
+                              from warn=N minutes before it starts. Format after ';': %s summary, \
+                              %t start time, %i time until the event",
+                             "~/.calendars,~/work.ics warn=10;%t %s (in %i)",
+                             agenda::make_agenda_metric);
+    registry.register_metric("mail",
+                             "Counts unread mail in Maildir folders and mbox files, given as \
+                              [LABEL=]PATH. 'hidezero' hides folders without unread mail. Format \
+                              per folder after ';': %l label, %n unread, %N new",
+                             "inbox=~/Mail/INBOX,~/mbox hidezero;%l:%n",
+                             mail::make_mail_metric);
+    registry.register_metric("window_title",
+                             "Shows the title of the focused window (i3 or sway), truncated to \
+                              the given number of characters",
+                             "60",
+                             i3::make_window_title_metric);
+    registry.register_metric("binding_mode",
+                             "Shows the current i3/sway binding mode; hidden in the default mode",
+                             "",
+                             i3::make_binding_mode_metric);
+    registry.register_metric("scratchpad",
+                             "Shows the number of windows on the i3/sway scratchpad (%n)",
+                             "scratchpad: %n",
+                             i3::make_scratchpad_metric);
+    registry.register_metric("mpd",
+                             "Shows what MPD is playing; address like mpc ($MPD_HOST/$MPD_PORT by \
+                              default), format after ';': %a artist, %t title, %A album, %e \
+                              elapsed, %d duration, %s state, %v volume. Click to play/pause, \
+                              middle/right-click for previous/next song",
+                             "localhost:6600;%s %a - %t [%e/%d]",
+                             mpd::make_mpd_metric);
+    registry.register_metric("volume",
+                             "Shows the master volume of PulseAudio/PipeWire (pactl) or ALSA \
+                              (amixer). Options: pulse|alsa, device=SINK|CONTROL, step=N, \
+                              muted=COLOR; format after ';' with %v. Scroll to change the volume, \
+                              click to mute",
+                             "pulse step=5 muted=#888888;vol %v%%",
+                             volume::make_volume_metric);
+    registry.register_metric("backlight",
+                             "Shows the backlight brightness of a device in /sys/class/backlight \
+                              (first one if omitted); scroll to change it by step=N percent. \
+                              Format after ';' with %p",
+                             "intel_backlight step=5;☀ %p%%",
+                             backlight::make_backlight_metric);
+    registry.register_metric("kbd",
+                             "Shows the keyboard layout (sway or X11) and the active lock keys; \
+                              urgent while caps lock is on. Options keyboard=ID, \
+                              locks=caps,num,scroll; format after ';' with %l, %k, %c, %n, %s",
+                             "locks=caps,num;%l %k",
+                             kbd::make_kbd_metric);
+    registry.register_metric("top",
+                             "Shows the N processes using the most CPU (cpu, default) or memory \
+                              (mem); len=N limits names. Format after ';' with %n, %p, %c, %m",
+                             "3 cpu len=10;%n %c%%",
+                             top::make_top_metric);
+    registry.register_metric("proc",
+                             "Shows whether processes are running, given as [LABEL=]SPEC: a \
+                              name, /regex/ on the command line or a PID file; red and urgent \
+                              if one is missing. Format after ';' with %l, %n, %c, %m",
+                             "sshd,web=/gunicorn: master/,/run/crond.pid;%l %n",
+                             proc_watch::make_proc_metric);
+    registry.register_metric("systemd",
+                             "Shows the number of failed systemd units (system and user manager, \
+                              or only 'system'/'user') and the state of the given units; \
+                              urgent if any failed. Format after ';' with %f, %F, %u",
+                             "sshd,user:syncthing;failed: %f %u",
+                             systemd::make_systemd_metric);
+    registry.register_metric("containers",
+                             "Shows running/all Docker or Podman containers and unhealthy ones; \
+                              options socket=PATH, libvirt[=URI] to count VMs. Format after ';' \
+                              with %r, %t, %u, %v, %V",
+                             "libvirt;ctr %r/%t %u",
+                             containers::make_containers_metric);
 }
 
 fn register_renderers(registry: &mut Config) {
@@ -179,11 +474,100 @@ fn register_renderers(registry: &mut Config) {
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let mut cfg = Config::new();
+    let (tx, rx) = channel();
+    let mut cfg = Config::new(tx.clone());
     register_metrics(&mut cfg);
     register_renderers(&mut cfg);
     let (renderer, selected_metrics, interval) = cfg.evaluate(&args[1..]);
 
+    signals::install_handlers();
+
+    render_loop(renderer, selected_metrics, interval, &mut cfg, (tx, rx));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
 
-    render_loop(renderer, selected_metrics, interval);
+    struct NopMetric;
+
+    impl Metric for NopMetric {
+        fn init(&mut self, _: &mut MetricState, _: Option<String>) {}
+        fn render(&mut self, _: &mut MetricState) -> RenderResult {
+            RenderResult::new(String::new(), Color::Default)
+        }
+    }
+
+    fn make_nop_metric() -> Box<Metric> {
+        CREATED.fetch_add(1, Ordering::SeqCst);
+        Box::new(NopMetric)
+    }
+
+    fn config() -> Config {
+        let (tx, _) = channel();
+        let mut cfg = Config::new(tx);
+        cfg.register_metric("nop", "Does nothing", "", make_nop_metric);
+        cfg.register_metric("other", "Does nothing either", "", make_nop_metric);
+        cfg
+    }
+
+    fn write_config(name: &str, content: &str) -> String {
+        let path = format!("{}/myi3stat-test-{}-{}.conf",
+                           env::temp_dir().display(),
+                           process::id(),
+                           name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| String::from(*a)).collect()
+    }
+
+    #[test]
+    fn test_read_config_file() {
+        let path = write_config("read",
+                                "# comment\n\n  --interval 2000\nclock  %a %d.%m. %H:%M  \nnop\n");
+        assert_eq!(Config::read_config_file(&path),
+                   Ok(strings(&["--interval=2000", "--clock=%a %d.%m. %H:%M", "--nop"])));
+        let _ = fs::remove_file(&path);
+
+        assert!(Config::read_config_file("/nonexistent/myi3stat.conf").is_err());
+    }
+
+    #[test]
+    fn test_command_line_overrides_config_file() {
+        let cfg = config();
+        let path = write_config("override", "--interval 2000\n--nop file\n--other\n");
+
+        let matches = cfg.parse(&strings(&["--config", &path, "--nop=cmdline"])).unwrap();
+        assert_eq!(matches.opt_str("nop"), Some(String::from("cmdline")));
+        assert_eq!(matches.opt_str("interval"), Some(String::from("2000")));
+        assert!(matches.opt_present("other"));
+
+        let matches = cfg.parse(&strings(&["--interval", "500", "--config", &path])).unwrap();
+        assert_eq!(matches.opt_str("interval"), Some(String::from("500")));
+        assert_eq!(matches.opt_str("nop"), Some(String::from("file")));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_select_metrics_reuses_metrics() {
+        let cfg = config();
+        let matches = cfg.parse(&strings(&["--nop=a", "--other"])).unwrap();
+        let before = CREATED.load(Ordering::SeqCst);
+        let metrics = cfg.select_metrics(&matches, Vec::new());
+        assert_eq!(CREATED.load(Ordering::SeqCst), before + 2);
+
+        // "other" is unchanged and kept; "nop" has a new argument and is created anew.
+        let matches = cfg.parse(&strings(&["--nop=b", "--other"])).unwrap();
+        let metrics = cfg.select_metrics(&matches, metrics);
+        assert_eq!(CREATED.load(Ordering::SeqCst), before + 3);
+        let args: Vec<Option<String>> = metrics.iter().map(|m| m.arg().clone()).collect();
+        assert_eq!(args, vec![Some(String::from("b")), None]);
+    }
 }
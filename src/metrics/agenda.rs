@@ -0,0 +1,715 @@
+//! Shows the next upcoming event from local iCalendar files (single .ics files or vdir
+//! directories, as synced by vdirsyncer).
+
+use framework::*;
+use helper::{commaseparated_to_vec, expand_home, format_template, read_file};
+use tz::{format_time, LocaleNames, TimeZone};
+
+extern crate chrono;
+use self::chrono::{Datelike, NaiveDate, NaiveDateTime};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+const DEFAULT_FMT: &'static str = "%t %s (in %i)";
+/// Default number of minutes before an event at which the block turns urgent.
+const DEFAULT_WARN: i64 = 10;
+/// Minimum time between scans for changed calendar files, in milliseconds.
+const RESCAN_INTERVAL: i64 = 60000;
+/// Upper bound on the number of recurrence periods looked at per event.
+const MAX_PERIODS: i64 = 100000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The supported subset of an RRULE: FREQ, INTERVAL, COUNT, UNTIL and BYDAY (plain weekdays for
+/// daily and weekly rules, optionally with an ordinal like 2TU or -1FR for monthly rules).
+#[derive(Clone, Debug, PartialEq)]
+struct RRule {
+    freq: Freq,
+    interval: i64,
+    count: Option<i64>,
+    /// Epoch seconds.
+    until: Option<i64>,
+    /// (ordinal, weekday counted from Monday = 0); ordinal 0 means every such weekday.
+    by_day: Vec<(i64, u32)>,
+}
+
+/// Name, parameters and value of a content line.
+type Property = (String, BTreeMap<String, String>, String);
+
+#[derive(Clone)]
+struct Event {
+    summary: String,
+    /// Start as local wall-clock time of the event's zone, in seconds since the epoch.
+    local_start: i64,
+    /// None for times given in UTC.
+    zone: Option<TimeZone>,
+    rrule: Option<RRule>,
+    /// Excluded occurrences, in epoch seconds.
+    exdates: Vec<i64>,
+}
+
+/// Joins folded lines (continuation lines start with a space or tab).
+fn unfold(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in content.split('\n') {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&line[1..]);
+                continue;
+            }
+        }
+        lines.push(String::from(line));
+    }
+    lines
+}
+
+/// Splits a content line into name, parameters and value:
+/// "DTSTART;TZID=Europe/Berlin:20170101T100000".
+fn split_property(line: &str) -> Option<(String, BTreeMap<String, String>, &str)> {
+    let mut in_quotes = false;
+    let colon = line.char_indices()
+                    .find(|&(_, c)| {
+                        if c == '"' {
+                            in_quotes = !in_quotes;
+                        }
+                        c == ':' && !in_quotes
+                    })
+                    .map(|(i, _)| i)?;
+
+    let mut params = BTreeMap::new();
+    let mut parts = line[..colon].split(';');
+    let name = parts.next().unwrap_or("").to_uppercase();
+    for param in parts {
+        let mut kv = param.splitn(2, '=');
+        if let (Some(k), Some(v)) = (kv.next(), kv.next()) {
+            params.insert(k.to_uppercase(), String::from(v.trim_matches('"')));
+        }
+    }
+    Some((name, params, &line[colon + 1..]))
+}
+
+fn unescape_text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push(' '),
+            Some(c) => out.push(c),
+            None => (),
+        }
+    }
+    out
+}
+
+/// Parses a DATE or DATE-TIME value into local wall-clock seconds, and whether it is in UTC.
+fn parse_datetime(value: &str) -> Option<(i64, bool)> {
+    let value = value.trim();
+    if value.len() == 8 {
+        return NaiveDate::parse_from_str(value, "%Y%m%d")
+                   .ok()
+                   .map(|d| (d.and_hms(0, 0, 0).timestamp(), false));
+    }
+    let utc = value.ends_with('Z');
+    NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S")
+        .ok()
+        .map(|dt| (dt.timestamp(), utc))
+}
+
+fn parse_weekday(s: &str) -> Option<u32> {
+    ["MO", "TU", "WE", "TH", "FR", "SA", "SU"].iter().position(|&d| d == s).map(|i| i as u32)
+}
+
+/// Parses an RRULE value; zone is used for an UNTIL given in local time.
+fn parse_rrule(value: &str, zone: Option<&TimeZone>) -> Option<RRule> {
+    let mut rule = RRule {
+        freq: Freq::Daily,
+        interval: 1,
+        count: None,
+        until: None,
+        by_day: Vec::new(),
+    };
+    let mut freq = None;
+
+    for part in value.split(';') {
+        let mut kv = part.splitn(2, '=');
+        let (k, v) = match (kv.next(), kv.next()) {
+            (Some(k), Some(v)) => (k, v),
+            _ => continue,
+        };
+        match k {
+            "FREQ" => {
+                freq = match v {
+                    "DAILY" => Some(Freq::Daily),
+                    "WEEKLY" => Some(Freq::Weekly),
+                    "MONTHLY" => Some(Freq::Monthly),
+                    "YEARLY" => Some(Freq::Yearly),
+                    // SECONDLY, MINUTELY and HOURLY aren't supported.
+                    _ => return None,
+                }
+            }
+            "INTERVAL" => rule.interval = i64::from_str(v).ok().filter(|&i| i > 0)?,
+            "COUNT" => rule.count = i64::from_str(v).ok(),
+            "UNTIL" => {
+                rule.until = parse_datetime(v).map(|(t, utc)| {
+                    let t = if v.len() == 8 { t + 86399 } else { t };
+                    match (utc, zone) {
+                        (false, Some(zone)) => zone.utc_from_local(t),
+                        _ => t,
+                    }
+                })
+            }
+            "BYDAY" => {
+                for day in v.split(',') {
+                    let split = day.len().saturating_sub(2);
+                    let ordinal = if split > 0 { i64::from_str(&day[..split]).ok()? } else { 0 };
+                    rule.by_day.push((ordinal, parse_weekday(&day[split..])?));
+                }
+            }
+            // BYMONTH, BYMONTHDAY, BYSETPOS etc. are ignored.
+            _ => (),
+        }
+    }
+    rule.freq = freq?;
+    Some(rule)
+}
+
+/// Parses all VEVENTs of a calendar. Times without TZID and unknown zones are taken to be in
+/// local time.
+fn parse_calendar(content: &str,
+                  local: &TimeZone,
+                  zones: &mut BTreeMap<String, Option<TimeZone>>)
+                  -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut props: Option<Vec<Property>> = None;
+
+    for line in unfold(content) {
+        let (name, params, value) = match split_property(&line) {
+            Some(p) => p,
+            None => continue,
+        };
+        match (name.as_str(), value) {
+            ("BEGIN", "VEVENT") => props = Some(Vec::new()),
+            ("END", "VEVENT") => {
+                if let Some(event) = props.take().and_then(|p| make_event(p, local, zones)) {
+                    events.push(event);
+                }
+            }
+            _ => {
+                if let Some(ref mut p) = props {
+                    p.push((name, params, String::from(value)));
+                }
+            }
+        }
+    }
+    events
+}
+
+fn make_event(props: Vec<Property>,
+              local: &TimeZone,
+              zones: &mut BTreeMap<String, Option<TimeZone>>)
+              -> Option<Event> {
+    let mut zone_of = |params: &BTreeMap<String, String>| -> TimeZone {
+        params.get("TZID")
+              .and_then(|tzid| {
+                  zones.entry(tzid.clone())
+                       .or_insert_with(|| TimeZone::load(tzid).ok())
+                       .clone()
+              })
+              .unwrap_or(local.clone())
+    };
+
+    let mut summary = String::new();
+    let mut start = None;
+    let mut rrule = None;
+    let mut exdates = Vec::new();
+
+    for &(ref name, ref params, ref value) in props.iter() {
+        match name.as_str() {
+            "SUMMARY" => summary = unescape_text(value),
+            "STATUS" if value == "CANCELLED" => return None,
+            "DTSTART" => {
+                let (t, utc) = parse_datetime(value)?;
+                start = Some((t, if utc { None } else { Some(zone_of(params)) }));
+            }
+            _ => (),
+        }
+    }
+    let (local_start, zone) = start?;
+
+    for &(ref name, ref params, ref value) in props.iter() {
+        match name.as_str() {
+            "RRULE" => rrule = parse_rrule(value, zone.as_ref()),
+            "EXDATE" => {
+                let exzone = zone_of(params);
+                for v in value.split(',') {
+                    if let Some((t, utc)) = parse_datetime(v) {
+                        exdates.push(if utc { t } else { exzone.utc_from_local(t) });
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Some(Event {
+        summary: summary,
+        local_start: local_start,
+        zone: zone,
+        rrule: rrule,
+        exdates: exdates,
+    })
+}
+
+fn to_date(local: i64) -> NaiveDate {
+    NaiveDateTime::from_timestamp(local.div_euclid(86400) * 86400, 0).date()
+}
+
+fn from_date(date: NaiveDate) -> i64 {
+    date.and_hms(0, 0, 0).timestamp()
+}
+
+/// Returns the day of month of the n-th (negative: counted from the end) weekday in a month.
+fn nth_weekday(year: i32, month: u32, n: i64, weekday: u32) -> Option<NaiveDate> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let (ny, nm) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    let days = (from_date(NaiveDate::from_ymd_opt(ny, nm, 1)?) - from_date(first)) / 86400;
+    let first_wd = first.weekday().num_days_from_monday();
+    let first_match = (weekday as i64 - first_wd as i64).rem_euclid(7);
+
+    let day = if n > 0 {
+        first_match + 7 * (n - 1)
+    } else {
+        let last_match = first_match + 7 * ((days - 1 - first_match) / 7);
+        last_match + 7 * (n + 1)
+    };
+    if day < 0 || day >= days {
+        return None;
+    }
+    NaiveDate::from_ymd_opt(year, month, day as u32 + 1)
+}
+
+/// Returns the local start times of all occurrences in the given recurrence period, in order.
+fn period_occurrences(start: i64, rule: &RRule, period: i64) -> Vec<i64> {
+    let date = to_date(start);
+    let time_of_day = start.rem_euclid(86400);
+    let step = period * rule.interval;
+
+    let mut occ = match rule.freq {
+        Freq::Daily if rule.by_day.is_empty() => vec![start + step * 86400],
+        // BYDAY limits a daily rule to the given weekdays.
+        Freq::Daily => {
+            let t = start + step * 86400;
+            let wd = to_date(t).weekday().num_days_from_monday();
+            if rule.by_day.iter().any(|&(_, d)| d == wd) { vec![t] } else { Vec::new() }
+        }
+        Freq::Weekly if rule.by_day.is_empty() => vec![start + step * 7 * 86400],
+        Freq::Weekly => {
+            let monday = from_date(date) - date.weekday().num_days_from_monday() as i64 * 86400 +
+                         step * 7 * 86400;
+            rule.by_day
+                .iter()
+                .map(|&(_, wd)| monday + wd as i64 * 86400 + time_of_day)
+                .collect()
+        }
+        Freq::Monthly => {
+            let months = date.year() as i64 * 12 + date.month0() as i64 + step;
+            let (y, m) = (months.div_euclid(12) as i32, months.rem_euclid(12) as u32 + 1);
+            if rule.by_day.is_empty() {
+                NaiveDate::from_ymd_opt(y, m, date.day())
+                    .map(|d| vec![from_date(d) + time_of_day])
+                    .unwrap_or(Vec::new())
+            } else {
+                // Without an ordinal, every such weekday of the month.
+                rule.by_day
+                    .iter()
+                    .flat_map(|&(n, wd)| {
+                        let ordinals = if n == 0 { vec![1, 2, 3, 4, 5] } else { vec![n] };
+                        ordinals.into_iter().filter_map(move |n| nth_weekday(y, m, n, wd))
+                    })
+                    .map(|d| from_date(d) + time_of_day)
+                    .collect()
+            }
+        }
+        Freq::Yearly => {
+            NaiveDate::from_ymd_opt(date.year() + step as i32, date.month(), date.day())
+                .map(|d| vec![from_date(d) + time_of_day])
+                .unwrap_or(Vec::new())
+        }
+    };
+    occ.sort();
+    occ.retain(|&t| t >= start);
+    occ
+}
+
+impl Event {
+    fn to_utc(&self, local: i64) -> i64 {
+        match self.zone {
+            None => local,
+            Some(ref zone) => zone.utc_from_local(local),
+        }
+    }
+
+    /// Returns the start (epoch seconds) of the first occurrence starting at or after now.
+    fn next_occurrence(&self, now: i64) -> Option<i64> {
+        let rule = match self.rrule {
+            None => {
+                let t = self.to_utc(self.local_start);
+                return if t >= now { Some(t) } else { None };
+            }
+            Some(ref rule) => rule,
+        };
+
+        // Without COUNT, skip the periods certainly lying in the past.
+        let mut period = 0;
+        if rule.count.is_none() {
+            let period_len = match rule.freq {
+                Freq::Daily => 86400 * rule.interval,
+                Freq::Weekly => 7 * 86400 * rule.interval,
+                _ => 0,
+            };
+            if period_len > 0 {
+                period = ((now - self.local_start) / period_len - 1).max(0);
+            }
+        }
+
+        let mut seen = 0;
+        while period < MAX_PERIODS {
+            for local in period_occurrences(self.local_start, rule, period) {
+                let t = self.to_utc(local);
+                seen += 1;
+                if rule.count.map_or(false, |c| seen > c) || rule.until.map_or(false, |u| t > u) {
+                    return None;
+                }
+                if t >= now && !self.exdates.contains(&t) {
+                    return Some(t);
+                }
+            }
+            period += 1;
+        }
+        None
+    }
+}
+
+/// Formats the time until an event, e.g. "2d 3h", "1h 05m" or "12m".
+fn format_until(secs: i64) -> String {
+    let mins = (secs + 59) / 60;
+    if mins >= 1440 {
+        format!("{}d {}h", mins / 1440, (mins / 60) % 24)
+    } else if mins >= 60 {
+        format!("{}h {:02}m", mins / 60, mins % 60)
+    } else {
+        format!("{}m", mins)
+    }
+}
+
+/// Collects the .ics files below path (a file, a vdir collection or a directory of collections)
+/// with their modification times.
+fn find_calendars(path: &str, depth: u32, out: &mut Vec<(String, Option<SystemTime>)>) {
+    let meta = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(_) => return,
+    };
+    if meta.is_file() {
+        out.push((String::from(path), meta.modified().ok()));
+        return;
+    }
+    if !meta.is_dir() || depth == 0 {
+        return;
+    }
+    if let Ok(entries) = fs::read_dir(path) {
+        let mut names: Vec<String> = entries.filter_map(|e| e.ok())
+                                            .filter_map(|e| e.file_name().into_string().ok())
+                                            .filter(|n| !n.starts_with('.'))
+                                            .collect();
+        names.sort();
+        for name in names {
+            let sub = format!("{}/{}", path, name);
+            if name.ends_with(".ics") || fs::metadata(&sub).map(|m| m.is_dir()).unwrap_or(false) {
+                find_calendars(&sub, depth - 1, out);
+            }
+        }
+    }
+}
+
+struct AgendaMetric {
+    paths: Vec<String>,
+    fmt: String,
+    /// Seconds before an event at which the block turns urgent.
+    warn: i64,
+    local: Option<TimeZone>,
+    names: Option<LocaleNames>,
+
+    files: Vec<(String, Option<SystemTime>)>,
+    scanned: i64,
+    events: Vec<Event>,
+    /// Start and index of the next event, valid until it has started.
+    next: Option<(i64, usize)>,
+    error: Option<String>,
+}
+
+impl AgendaMetric {
+    /// Re-reads the calendars if files were added, removed or modified.
+    fn rescan(&mut self, local: &TimeZone) {
+        let mut files = Vec::new();
+        for path in self.paths.iter() {
+            find_calendars(path, 2, &mut files);
+        }
+        if files == self.files && !self.events.is_empty() {
+            return;
+        }
+
+        let mut zones = BTreeMap::new();
+        self.events = files.iter()
+                           .filter_map(|&(ref path, _)| read_file(path))
+                           .flat_map(|c| parse_calendar(&c, local, &mut zones))
+                           .collect();
+        self.files = files;
+        self.next = None;
+    }
+
+    fn find_next(&self, now: i64) -> Option<(i64, usize)> {
+        self.events
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.next_occurrence(now).map(|t| (t, i)))
+            .min()
+    }
+}
+
+impl Metric for AgendaMetric {
+    // arg is a comma-separated list of .ics files or vdir directories, optionally followed by
+    // "warn=N" (minutes before an event to become urgent), and after ';' a format with %s summary,
+    // %t start time and %i time until the event: "~/.calendars warn=15;%s at %t"
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        let arg = arg.unwrap_or(String::new());
+        let mut parts = arg.splitn(2, ';');
+        let mut words = parts.next().unwrap_or("").split_whitespace();
+
+        self.paths = commaseparated_to_vec(String::from(words.next().unwrap_or("")))
+                         .iter()
+                         .map(|p| expand_home(p.trim()))
+                         .collect();
+        for word in words {
+            match word.splitn(2, '=').collect::<Vec<&str>>().as_slice() {
+                ["warn", n] => {
+                    match i64::from_str(n) {
+                        Ok(n) => self.warn = n * 60,
+                        Err(_) => self.error = Some(format!("agenda: invalid warn '{}'", n)),
+                    }
+                }
+                _ => self.error = Some(format!("agenda: unknown option '{}'", word)),
+            }
+        }
+        if let Some(fmt) = parts.next() {
+            self.fmt = String::from(fmt);
+        }
+        if self.paths.is_empty() {
+            self.error = Some(String::from("agenda: no calendars given"));
+        }
+        self.local = Some(TimeZone::local());
+        self.names = Some(LocaleNames::from_env());
+    }
+
+    fn render(&mut self, st: &mut MetricState) -> RenderResult {
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
+
+        let now_ms = MetricState::now();
+        let now = now_ms / 1000;
+        let local = self.local.take().unwrap();
+        if now_ms - self.scanned >= RESCAN_INTERVAL {
+            self.rescan(&local);
+            self.scanned = now_ms;
+        }
+        if self.files.is_empty() {
+            self.local = Some(local);
+            return RenderResult::new(String::from("agenda n/a"), Color::Red);
+        }
+        if self.next.map_or(true, |(t, _)| t < now) {
+            self.next = self.find_next(now);
+            // Turn urgent on time, and move on to the following event once this one has started.
+            if let Some((start, _)) = self.next {
+                if start - self.warn > now {
+                    st.wake_at((start - self.warn) * 1000);
+                }
+                st.wake_at((start + 1) * 1000);
+            }
+        }
+
+        let result = match self.next {
+            None => RenderResult::new(String::from("no events"), Color::Default),
+            Some((start, i)) => {
+                let names = self.names.as_ref().unwrap();
+                let same_day = format_time(start, &local, names, "%Y%m%d") ==
+                               format_time(now, &local, names, "%Y%m%d");
+                let start_fmt = if same_day { "%H:%M" } else { "%a %H:%M" };
+
+                let mut values = BTreeMap::new();
+                values.insert('s', self.events[i].summary.clone());
+                values.insert('t', format_time(start, &local, names, start_fmt));
+                values.insert('i', format_until(start - now));
+
+                RenderResult::new(format_template(&self.fmt, &values), Color::Default)
+                    .with_urgent(start - now <= self.warn)
+            }
+        };
+        self.local = Some(local);
+        result
+    }
+}
+
+pub fn make_agenda_metric() -> Box<Metric> {
+    Box::new(AgendaMetric {
+        paths: Vec::new(),
+        fmt: String::from(DEFAULT_FMT),
+        warn: DEFAULT_WARN * 60,
+        local: None,
+        names: None,
+        files: Vec::new(),
+        scanned: i64::min_value() / 2,
+        events: Vec::new(),
+        next: None,
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_until, parse_calendar, Freq};
+    use tz::TimeZone;
+
+    use std::collections::BTreeMap;
+
+    const CALENDAR: &'static str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:1\r
+DTSTART:20170110T090000Z\r
+SUMMARY:Dentist\\, again\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:2\r
+DTSTART:20170102T100000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,TH;COUNT=4\r
+SUMMARY:Stand\r
+ up\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:3\r
+DTSTART;VALUE=DATE:20170110\r
+RRULE:FREQ=MONTHLY;BYDAY=-1FR\r
+EXDATE;VALUE=DATE:20170127\r
+SUMMARY:Pub\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:4\r
+DTSTART:20170101T120000\r
+RRULE:FREQ=DAILY;INTERVAL=2;UNTIL=20170105T235959Z\r
+SUMMARY:Gym\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:5\r
+DTSTART:20170101T120000Z\r
+STATUS:CANCELLED\r
+SUMMARY:Cancelled\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    /// 2017-01-01 00:00 UTC
+    const JAN1: i64 = 1483228800;
+    const DAY: i64 = 86400;
+
+    #[test]
+    fn test_parse_calendar() {
+        let events = parse_calendar(CALENDAR, &TimeZone::utc(), &mut BTreeMap::new());
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].summary, "Dentist, again");
+        assert_eq!(events[1].summary, "Standup");
+        assert_eq!(events[1].rrule.as_ref().map(|r| r.freq), Some(Freq::Weekly));
+        assert_eq!(events[1].rrule.as_ref().map(|r| r.by_day.clone()),
+                   Some(vec![(0, 0), (0, 3)]));
+        assert_eq!(events[2].rrule.as_ref().map(|r| r.by_day.clone()), Some(vec![(-1, 4)]));
+    }
+
+    #[test]
+    fn test_next_occurrence() {
+        let events = parse_calendar(CALENDAR, &TimeZone::utc(), &mut BTreeMap::new());
+
+        // single event
+        assert_eq!(events[0].next_occurrence(JAN1), Some(JAN1 + 9 * DAY + 9 * 3600));
+        assert_eq!(events[0].next_occurrence(JAN1 + 10 * DAY), None);
+
+        // Mondays and Thursdays, four times: Jan 2, 5, 9, 12
+        let standup = &events[1];
+        assert_eq!(standup.next_occurrence(JAN1), Some(JAN1 + DAY + 10 * 3600));
+        assert_eq!(standup.next_occurrence(JAN1 + 2 * DAY), Some(JAN1 + 4 * DAY + 10 * 3600));
+        assert_eq!(standup.next_occurrence(JAN1 + 9 * DAY), Some(JAN1 + 11 * DAY + 10 * 3600));
+        assert_eq!(standup.next_occurrence(JAN1 + 12 * DAY), None);
+
+        // last Friday of the month, except January: Feb 24
+        let pub_night = &events[2];
+        assert_eq!(pub_night.next_occurrence(JAN1 + 11 * DAY), Some(JAN1 + 54 * DAY));
+
+        // every other day until Jan 5: Jan 1, 3, 5
+        let gym = &events[3];
+        assert_eq!(gym.next_occurrence(JAN1 + DAY), Some(JAN1 + 2 * DAY + 12 * 3600));
+        assert_eq!(gym.next_occurrence(JAN1 + 3 * DAY), Some(JAN1 + 4 * DAY + 12 * 3600));
+        assert_eq!(gym.next_occurrence(JAN1 + 5 * DAY), None);
+    }
+
+    #[test]
+    fn test_byday_expansion() {
+        let calendar = "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+DTSTART:20170102T080000\r
+RRULE:FREQ=MONTHLY;BYDAY=MO,WE\r
+SUMMARY:Review\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART:20170102T073000\r
+RRULE:FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR\r
+SUMMARY:Commute\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+        let events = parse_calendar(calendar, &TimeZone::utc(), &mut BTreeMap::new());
+
+        // Every Monday and Wednesday of the month: Jan 30 (Mon), Feb 1 (Wed)
+        let review = &events[0];
+        assert_eq!(review.next_occurrence(JAN1 + 26 * DAY), Some(JAN1 + 29 * DAY + 8 * 3600));
+        assert_eq!(review.next_occurrence(JAN1 + 30 * DAY), Some(JAN1 + 31 * DAY + 8 * 3600));
+
+        // Weekdays only: after Friday, Jan 6 comes Monday, Jan 9
+        let commute = &events[1];
+        assert_eq!(commute.next_occurrence(JAN1 + 5 * DAY + 8 * 3600),
+                   Some(JAN1 + 8 * DAY + 7 * 3600 + 1800));
+        assert_eq!(commute.next_occurrence(JAN1 + 9 * DAY),
+                   Some(JAN1 + 9 * DAY + 7 * 3600 + 1800));
+    }
+
+    #[test]
+    fn test_format_until() {
+        assert_eq!(format_until(30), "1m");
+        assert_eq!(format_until(3900), "1h 05m");
+        assert_eq!(format_until(2 * 86400 + 3 * 3600), "2d 3h");
+    }
+}
@@ -0,0 +1,180 @@
+//! Shows the display backlight brightness; scrolling adjusts it.
+
+use framework::*;
+use helper::{format_template, read_sysfs_file, run_command};
+use inotify;
+use inotify::Inotify;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::str::FromStr;
+
+const DEFAULT_FMT: &'static str = "☀ %p%%";
+const DEFAULT_STEP: u64 = 5;
+
+/// Brightness in percent of max, rounded.
+fn percent(brightness: u64, max: u64) -> u64 {
+    if max == 0 { 0 } else { (200 * brightness + max) / (2 * max) }
+}
+
+/// Returns the brightness after a scroll step of step percent; never switches the backlight off
+/// completely.
+fn step_brightness(brightness: u64, max: u64, step: u64, up: bool) -> u64 {
+    let delta = (max * step / 100).max(1);
+    let new = if up { brightness + delta } else { brightness.saturating_sub(delta) };
+    new.max(1).min(max)
+}
+
+struct BacklightMetric {
+    device: Option<String>,
+    fmt: String,
+    step: u64,
+    inotify: Option<Inotify>,
+}
+
+impl BacklightMetric {
+    fn first_device() -> Option<String> {
+        let mut devices: Vec<String> = fs::read_dir("/sys/class/backlight")
+                                           .ok()?
+                                           .filter_map(|e| e.ok())
+                                           .filter_map(|e| e.file_name().into_string().ok())
+                                           .collect();
+        devices.sort();
+        devices.into_iter().next()
+    }
+
+    fn read_attr(&self, attr: &str) -> Option<u64> {
+        let device = self.device.as_ref()?;
+        read_sysfs_file(format!("/class/backlight/{}/{}", device, attr))
+            .and_then(|v| u64::from_str(&v).ok())
+    }
+
+    /// Returns current and maximum brightness.
+    fn read(&self) -> Option<(u64, u64)> {
+        let brightness = self.read_attr("actual_brightness").or(self.read_attr("brightness"))?;
+        Some((brightness, self.read_attr("max_brightness")?))
+    }
+
+    /// Writes the brightness to sysfs, which usually needs root or a udev rule; otherwise asks
+    /// logind, which lets the user of the active session set it.
+    fn set(&self, brightness: u64) -> Result<(), String> {
+        let device = self.device.as_ref().ok_or(String::from("no backlight device"))?;
+        let path = format!("/sys/class/backlight/{}/brightness", device);
+
+        let written = fs::OpenOptions::new()
+                          .write(true)
+                          .open(&path)
+                          .and_then(|mut f| f.write_all(format!("{}", brightness).as_bytes()));
+        if written.is_ok() {
+            return Ok(());
+        }
+        run_command("busctl",
+                    &["call",
+                      "org.freedesktop.login1",
+                      "/org/freedesktop/login1/session/auto",
+                      "org.freedesktop.login1.Session",
+                      "SetBrightness",
+                      "ssu",
+                      "backlight",
+                      device,
+                      &format!("{}", brightness)])
+            .map(|_| ())
+            .ok_or(String::from("SetBrightness via logind failed"))
+    }
+}
+
+impl Metric for BacklightMetric {
+    // arg is the device in /sys/class/backlight (the first one if omitted) and "step=N", the
+    // percentage to change per scroll step; optionally followed by ';' and a format with %p for
+    // the brightness in percent: "intel_backlight step=10;light %p%%"
+    fn init(&mut self, st: &mut MetricState, arg: Option<String>) {
+        let arg = arg.unwrap_or(String::new());
+        let mut parts = arg.splitn(2, ';');
+
+        for word in parts.next().unwrap_or("").split_whitespace() {
+            if word.starts_with("step=") {
+                self.step = u64::from_str(&word[5..]).unwrap_or(DEFAULT_STEP);
+            } else {
+                self.device = Some(String::from(word));
+            }
+        }
+        if let Some(fmt) = parts.next() {
+            self.fmt = String::from(fmt);
+        }
+        if self.device.is_none() {
+            self.device = BacklightMetric::first_device();
+        }
+
+        // Writes to brightness, and changes by the hardware (sysfs_notify() on
+        // actual_brightness), show up as modifications.
+        if let (Some(device), Some(mut inotify)) = (self.device.clone(), Inotify::new()) {
+            for attr in ["brightness", "actual_brightness"].iter() {
+                let path = format!("/sys/class/backlight/{}/{}", device, attr);
+                inotify.add_watch(&path, inotify::IN_MODIFY);
+            }
+            st.watch_fd(inotify.fd());
+            self.inotify = Some(inotify);
+        }
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        if let Some(ref mut inotify) = self.inotify {
+            inotify.read_events();
+        }
+
+        match self.read() {
+            None => RenderResult::new(String::from("backlight n/a"), Color::Red),
+            Some((brightness, max)) => {
+                let mut values = BTreeMap::new();
+                values.insert('p', format!("{}", percent(brightness, max)));
+                RenderResult::new(format_template(&self.fmt, &values), Color::Default)
+            }
+        }
+    }
+
+    // Scrolling up and down (buttons 4 and 5) changes the brightness.
+    fn click(&mut self, _: &mut MetricState, ev: &ClickEvent) {
+        let up = match ev.button {
+            4 => true,
+            5 => false,
+            _ => return,
+        };
+        if let Some((brightness, max)) = self.read() {
+            if let Err(e) = self.set(step_brightness(brightness, max, self.step, up)) {
+                eprintln!("myi3stat: backlight: {}", e);
+            }
+        }
+    }
+}
+
+pub fn make_backlight_metric() -> Box<Metric> {
+    Box::new(BacklightMetric {
+        device: None,
+        fmt: String::from(DEFAULT_FMT),
+        step: DEFAULT_STEP,
+        inotify: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{percent, step_brightness};
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(percent(0, 937), 0);
+        assert_eq!(percent(468, 937), 50);
+        assert_eq!(percent(937, 937), 100);
+        assert_eq!(percent(5, 0), 0);
+    }
+
+    #[test]
+    fn test_step_brightness() {
+        assert_eq!(step_brightness(500, 1000, 5, true), 550);
+        assert_eq!(step_brightness(980, 1000, 5, true), 1000);
+        assert_eq!(step_brightness(30, 1000, 5, false), 1);
+        // Few steps: change by at least 1.
+        assert_eq!(step_brightness(3, 7, 5, true), 4);
+    }
+}
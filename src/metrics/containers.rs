@@ -0,0 +1,289 @@
+//! Shows the number of running and existing containers from the Docker or Podman API, unhealthy
+//! ones by name, and optionally the libvirt domains (virtual machines).
+
+use framework::*;
+use helper::{format_template, run_command};
+use json;
+use json::Json;
+use watch::Poller;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_FMT: &'static str = "ctr %r/%t %u";
+/// Time to wait for an API reply, in milliseconds.
+const TIMEOUT: u64 = 1000;
+/// Time between two libvirt queries, in milliseconds; each one runs virsh twice, which may take
+/// a while to connect, on a thread of its own.
+const LIBVIRT_REFRESH_INTERVAL: i64 = 30000;
+
+#[derive(Debug, Default, PartialEq)]
+struct Containers {
+    running: usize,
+    total: usize,
+    unhealthy: Vec<String>,
+}
+
+/// Returns the API sockets to try: $DOCKER_HOST if it's a unix:// URL, then the default locations
+/// of Docker and (rootless and rootful) Podman.
+fn default_sockets() -> Vec<String> {
+    let mut sockets = Vec::new();
+    if let Ok(host) = env::var("DOCKER_HOST") {
+        if host.starts_with("unix://") {
+            sockets.push(String::from(&host[7..]));
+        }
+    }
+    sockets.push(String::from("/var/run/docker.sock"));
+    if let Ok(dir) = env::var("XDG_RUNTIME_DIR") {
+        sockets.push(format!("{}/podman/podman.sock", dir));
+    }
+    sockets.push(String::from("/run/podman/podman.sock"));
+    sockets
+}
+
+/// Sends a GET request to the API and returns the body of a successful reply. HTTP/1.0 makes the
+/// server close the connection after a plain (not chunked) body.
+fn get(socket: &str, path: &str) -> Result<String, String> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| format!("{}: {}", socket, e))?;
+    stream.set_read_timeout(Some(Duration::from_millis(TIMEOUT)))
+          .map_err(|e| e.to_string())?;
+    stream.write_all(format!("GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).as_bytes())
+          .map_err(|e| e.to_string())?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).map_err(|e| e.to_string())?;
+    let status = reply.lines().next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("{}: {}", socket, status));
+    }
+    match reply.find("\r\n\r\n") {
+        None => Err(format!("{}: malformed reply", socket)),
+        Some(i) => Ok(String::from(&reply[i + 4..])),
+    }
+}
+
+/// Parses the reply of GET /containers/json?all=1. The health is only part of the status text,
+/// e.g. "Up 2 hours (unhealthy)".
+fn parse_containers(body: &str) -> Result<Containers, String> {
+    let list = json::parse(body)?;
+    let list = list.as_array().ok_or(String::from("unexpected reply"))?;
+    let mut c = Containers::default();
+
+    for container in list {
+        c.total += 1;
+        if container.get("State").and_then(Json::as_str) == Some("running") {
+            c.running += 1;
+        }
+        let status = container.get("Status").and_then(Json::as_str).unwrap_or("");
+        if status.contains("(unhealthy)") {
+            let name = container.get("Names")
+                                .and_then(Json::as_array)
+                                .and_then(|n| n.first())
+                                .and_then(Json::as_str)
+                                .unwrap_or("?");
+            c.unhealthy.push(String::from(name.trim_start_matches('/')));
+        }
+    }
+    Ok(c)
+}
+
+/// Returns the numbers of running and all libvirt domains, if virsh can connect to uri.
+fn libvirt_domains(uri: &str) -> Option<(usize, usize)> {
+    let count = |out: String| out.lines().filter(|l| !l.trim().is_empty()).count();
+    let running = run_command("virsh", &["-c", uri, "list", "--name"]).map(count)?;
+    let all = run_command("virsh", &["-c", uri, "list", "--all", "--name"]).map(count)?;
+    Some((running, all))
+}
+
+struct ContainersMetric {
+    /// API sockets; all distinct ones that exist are queried and their containers added up.
+    sockets: Vec<String>,
+    /// libvirt connection URI, if domains are to be counted.
+    libvirt: Option<String>,
+    /// Set if domains are counted; yields the running and all domains.
+    domains: Option<Poller<Option<(usize, usize)>>>,
+    fmt: String,
+    error: Option<String>,
+}
+
+impl ContainersMetric {
+    fn configure(&mut self, arg: &str) -> Result<(), String> {
+        let mut parts = arg.splitn(2, ';');
+
+        for word in parts.next().unwrap_or("").split_whitespace() {
+            let mut kv = word.splitn(2, '=');
+            match (kv.next().unwrap_or(""), kv.next()) {
+                ("socket", Some(path)) => self.sockets.push(String::from(path)),
+                ("libvirt", None) => self.libvirt = Some(String::from("qemu:///system")),
+                ("libvirt", Some(uri)) => self.libvirt = Some(String::from(uri)),
+                _ => return Err(format!("unknown option '{}'", word)),
+            }
+        }
+        if self.sockets.is_empty() {
+            self.sockets = default_sockets();
+        }
+        if let Some(fmt) = parts.next() {
+            self.fmt = String::from(fmt);
+        }
+        Ok(())
+    }
+
+    fn query(&self) -> Result<Containers, String> {
+        let mut result: Option<Containers> = None;
+        let mut error = String::from("no Docker or Podman socket found");
+
+        // With podman-docker, Docker's socket is a symlink to Podman's; query each socket once.
+        let mut sockets: Vec<PathBuf> = Vec::new();
+        for socket in self.sockets.iter().filter_map(|s| fs::canonicalize(s).ok()) {
+            if !sockets.contains(&socket) {
+                sockets.push(socket);
+            }
+        }
+
+        for socket in sockets.iter().filter_map(|s| s.to_str()) {
+            match get(socket, "/containers/json?all=1").and_then(|b| parse_containers(&b)) {
+                Err(e) => error = e,
+                Ok(c) => {
+                    let sum = result.get_or_insert(Containers::default());
+                    sum.running += c.running;
+                    sum.total += c.total;
+                    sum.unhealthy.extend(c.unhealthy);
+                }
+            }
+        }
+        result.ok_or(error)
+    }
+}
+
+impl Metric for ContainersMetric {
+    // arg consists of options: "socket=PATH" (API socket, may be repeated; by default Docker's
+    // and Podman's usual ones) and "libvirt" or "libvirt=URI" (count domains, by default of
+    // qemu:///system); optionally followed by ';' and a format with %r for the running and %t
+    // for all containers, %u for the names of unhealthy ones, %v for running and %V for all
+    // domains: "libvirt;ctr %r/%t %u vm %v"
+    fn init(&mut self, st: &mut MetricState, arg: Option<String>) {
+        if let Err(e) = self.configure(&arg.unwrap_or(String::new())) {
+            self.error = Some(format!("containers: {}", e));
+            return;
+        }
+        if let Some(uri) = self.libvirt.clone() {
+            self.domains = Some(Poller::start(move || libvirt_domains(&uri),
+                                              LIBVIRT_REFRESH_INTERVAL,
+                                              st.notifier()));
+        }
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
+        let containers = match self.query() {
+            Err(e) => {
+                // Without containers, domains may still be of interest.
+                if self.libvirt.is_none() {
+                    return RenderResult::new(format!("containers: {}", e), Color::Red);
+                }
+                Containers::default()
+            }
+            Ok(c) => c,
+        };
+        let domains = self.domains.as_ref().and_then(Poller::get).and_then(|d| d);
+
+        let mut values = BTreeMap::new();
+        values.insert('r', format!("{}", containers.running));
+        values.insert('t', format!("{}", containers.total));
+        values.insert('u', containers.unhealthy.join(","));
+        if let Some((running, all)) = domains {
+            values.insert('v', format!("{}", running));
+            values.insert('V', format!("{}", all));
+        } else {
+            values.insert('v', String::from("?"));
+            values.insert('V', String::from("?"));
+        }
+        let text = String::from(format_template(&self.fmt, &values).trim());
+
+        let color = if containers.unhealthy.is_empty() { Color::Default } else { Color::Red };
+        RenderResult::new(text, color)
+    }
+}
+
+pub fn make_containers_metric() -> Box<Metric> {
+    Box::new(ContainersMetric {
+        sockets: Vec::new(),
+        libvirt: None,
+        domains: None,
+        fmt: String::from(DEFAULT_FMT),
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use i3ipc::tests::listen;
+
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+
+    const CONTAINERS: &'static str = r#"[
+        {"Id":"8dfafdbc3a40","Names":["/web"],"State":"running","Status":"Up 2 hours (healthy)"},
+        {"Id":"4e0c1bd3f2a7","Names":["/db"],"State":"running","Status":"Up 2 hours (unhealthy)"},
+        {"Id":"a1b2c3d4e5f6","Names":["/old_build"],"State":"exited",
+         "Status":"Exited (0) 3 days ago"}]"#;
+
+    #[test]
+    fn test_api() {
+        let (listener, path) = listen("docker");
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            assert_eq!(request, "GET /containers/json?all=1 HTTP/1.0\r\n");
+            // Headers until the empty line.
+            while request.trim() != "" {
+                request.clear();
+                reader.read_line(&mut request).unwrap();
+            }
+            write!(reader.get_mut(),
+                   "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{}",
+                   CONTAINERS)
+                .unwrap();
+        });
+
+        // The same socket through a symlink must not be counted twice.
+        let link = format!("{}.link", path);
+        let _ = ::std::fs::remove_file(&link);
+        ::std::os::unix::fs::symlink(&path, &link).unwrap();
+
+        let mut metric = make_containers_metric();
+        let mut st = MetricState::new();
+        metric.init(&mut st,
+                    Some(format!("socket={} socket={} socket=/nonexistent.sock;%r/%t %u",
+                                 path,
+                                 link)));
+        let (text, color) = metric.render(&mut st).get();
+        assert_eq!(text, "2/3 db");
+        assert!(match color {
+            Color::Red => true,
+            _ => false,
+        });
+
+        server.join().unwrap();
+        let _ = ::std::fs::remove_file(&link);
+        let _ = ::std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        let mut metric = make_containers_metric();
+        let mut st = MetricState::new();
+        metric.init(&mut st, Some(String::from("libvrt")));
+        assert_eq!(metric.render(&mut st).get().0, "containers: unknown option 'libvrt'");
+    }
+}
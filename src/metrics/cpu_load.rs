@@ -1,5 +1,5 @@
 use framework::*;
-use helper::{get_procfs_file_lines, extract_from_str};
+use helper::{get_procfs_file_lines, extract_from_str, number_of_cores};
 
 extern crate regex;
 use self::regex::Regex;
@@ -18,7 +18,6 @@ struct CPULoadMetric {
     user_hz: u64,
     last_cpu_millis: u64,
 
-    individual_cpu_regex: Regex,
     total_cpu_regex: Regex,
 }
 
@@ -31,29 +30,10 @@ impl CPULoadMetric {
             ncpu: 1,
             user_hz: unsafe { sysconf(_SC_CLK_TCK) } as u64,
             last_cpu_millis: 0,
-            individual_cpu_regex: Regex::new(r"^cpu(\d+)\s+").unwrap(),
             // user nice system
             total_cpu_regex: Regex::new(r"^cpu\s+(\d+)\s+(\d+)\s+(\d+)").unwrap(),
         }
     }
-    fn get_number_of_cores(&self) -> i32 {
-        let lines = get_procfs_file_lines(String::from("/stat"));
-
-        match lines {
-            None => 1,
-            Some(lns) => {
-                let mut n = 0;
-                for line in lns {
-                    if self.individual_cpu_regex.is_match(&line) {
-                        n += 1;
-                    } else if n > 0 {
-                        break;
-                    }
-                }
-                n
-            }
-        }
-    }
     fn get_total_cpu_millis(&self) -> u64 {
         let lines = get_procfs_file_lines(String::from("/stat")).unwrap_or(Vec::new());
 
@@ -85,7 +65,7 @@ impl Metric for CPULoadMetric {
                 }
             }
         }
-        self.ncpu = self.get_number_of_cores();
+        self.ncpu = number_of_cores();
     }
     fn render(&mut self, st: &mut MetricState) -> RenderResult {
         // evaluation interval in milliseconds
@@ -102,6 +82,9 @@ impl Metric for CPULoadMetric {
 
         RenderResult::new(format!("{:4.0}%", percentage), Color::Default)
     }
+    fn reset(&mut self, _: &mut MetricState) {
+        self.last_cpu_millis = self.get_total_cpu_millis();
+    }
 }
 
 pub fn make_cpu_load_metric() -> Box<Metric> {
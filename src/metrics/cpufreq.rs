@@ -0,0 +1,168 @@
+//! Shows CPU frequencies and the scaling governor.
+
+use framework::*;
+use helper::{read_procfs_file, read_sysfs_file, scale_to_unit};
+
+use std::fs;
+use std::str::FromStr;
+
+#[derive(PartialEq)]
+enum DisplayMode {
+    /// Average over all cores.
+    Average,
+    /// Minimum, average and maximum over all cores.
+    Range,
+    /// Every core on its own.
+    PerCore,
+}
+
+struct CPUFreqMetric {
+    mode: DisplayMode,
+    show_governor: bool,
+    show_epp: bool,
+}
+
+/// Extracts the "cpu MHz" values (in kHz) from /proc/cpuinfo.
+fn parse_cpuinfo_freqs(content: &str) -> Vec<f64> {
+    content.lines()
+           .filter(|l| l.starts_with("cpu MHz"))
+           .filter_map(|l| l.splitn(2, ':').nth(1))
+           .filter_map(|mhz| f64::from_str(mhz.trim()).ok())
+           .map(|mhz| mhz * 1000.)
+           .collect()
+}
+
+/// Formats a frequency given in kHz, e.g. "3.40GHz".
+fn format_freq(khz: f64) -> String {
+    let (f, unit) = scale_to_unit(khz, 1000., &["kHz", "MHz", "GHz"]);
+    format!("{:.2}{}", f, unit)
+}
+
+impl CPUFreqMetric {
+    /// Returns the numbers of all CPUs that have a cpufreq directory, in ascending order.
+    fn cpus_with_cpufreq() -> Vec<u32> {
+        let mut cpus: Vec<u32> = match fs::read_dir("/sys/devices/system/cpu") {
+            Err(_) => return Vec::new(),
+            Ok(entries) => {
+                entries.filter_map(|e| e.ok())
+                       .filter_map(|e| e.file_name().into_string().ok())
+                       .filter(|n| n.starts_with("cpu"))
+                       .filter_map(|n| u32::from_str(&n[3..]).ok())
+                       .filter(|n| {
+                           fs::metadata(format!("/sys/devices/system/cpu/cpu{}/cpufreq", n))
+                               .is_ok()
+                       })
+                       .collect()
+            }
+        };
+        cpus.sort();
+        cpus
+    }
+
+    fn read_attr(cpu: u32, attr: &str) -> Option<String> {
+        read_sysfs_file(format!("/devices/system/cpu/cpu{}/cpufreq/{}", cpu, attr))
+    }
+
+    /// Returns the current frequency of every core in kHz.
+    fn get_freqs() -> Vec<f64> {
+        let freqs: Vec<f64> = CPUFreqMetric::cpus_with_cpufreq()
+                                  .into_iter()
+                                  .filter_map(|n| CPUFreqMetric::read_attr(n, "scaling_cur_freq"))
+                                  .filter_map(|f| f64::from_str(&f).ok())
+                                  .collect();
+        if !freqs.is_empty() {
+            return freqs;
+        }
+        parse_cpuinfo_freqs(&read_procfs_file(String::from("/cpuinfo")).unwrap_or(String::new()))
+    }
+
+    /// Reads an attribute of the first core; governor and preference are usually the same for
+    /// all cores.
+    fn read_first_attr(attr: &str) -> Option<String> {
+        CPUFreqMetric::cpus_with_cpufreq()
+            .first()
+            .and_then(|&n| CPUFreqMetric::read_attr(n, attr))
+    }
+}
+
+impl Metric for CPUFreqMetric {
+    // arg is the display mode ("avg", "range" or "percore"), optionally followed by "gov" to show
+    // the scaling governor and "epp" to show the energy performance preference.
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        let arg = arg.unwrap_or(String::new());
+
+        for word in arg.split_whitespace() {
+            match word {
+                "avg" => self.mode = DisplayMode::Average,
+                "range" => self.mode = DisplayMode::Range,
+                "percore" => self.mode = DisplayMode::PerCore,
+                "gov" => self.show_governor = true,
+                "epp" => self.show_epp = true,
+                _ => (),
+            }
+        }
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        let freqs = CPUFreqMetric::get_freqs();
+        if freqs.is_empty() {
+            return RenderResult::new(String::from("cpufreq n/a"), Color::Red);
+        }
+
+        let avg = freqs.iter().fold(0., |acc, f| acc + f) / freqs.len() as f64;
+        let mut parts = match self.mode {
+            DisplayMode::Average => vec![format_freq(avg)],
+            DisplayMode::Range => {
+                let min = freqs.iter().cloned().fold(freqs[0], f64::min);
+                let max = freqs.iter().cloned().fold(freqs[0], f64::max);
+                vec![format!("{}/{}/{}", format_freq(min), format_freq(avg), format_freq(max))]
+            }
+            DisplayMode::PerCore => freqs.iter().map(|&f| format_freq(f)).collect(),
+        };
+
+        let governor = CPUFreqMetric::read_first_attr("scaling_governor");
+        if self.show_governor {
+            parts.push(governor.clone().unwrap_or(String::from("?")));
+        }
+        if self.show_epp {
+            if let Some(epp) = CPUFreqMetric::read_first_attr("energy_performance_preference") {
+                parts.push(epp);
+            }
+        }
+
+        let color = if governor.as_ref().map_or(false, |g| g == "powersave") {
+            Color::Orange
+        } else {
+            Color::Default
+        };
+        RenderResult::new(parts.join(" "), color)
+    }
+}
+
+pub fn make_cpufreq_metric() -> Box<Metric> {
+    Box::new(CPUFreqMetric {
+        mode: DisplayMode::Average,
+        show_governor: false,
+        show_epp: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_freq, parse_cpuinfo_freqs};
+
+    #[test]
+    fn test_parse_cpuinfo_freqs() {
+        let cpuinfo = "processor\t: 0\ncpu MHz\t\t: 3400.000\ncache size\t: 8192 \
+                       KB\n\nprocessor\t: 1\ncpu MHz\t\t: 800.123\n";
+        assert_eq!(parse_cpuinfo_freqs(cpuinfo), vec![3400000., 800123.]);
+    }
+
+    #[test]
+    fn test_format_freq() {
+        assert_eq!(format_freq(3400000.), "3.40GHz");
+        assert_eq!(format_freq(800000.), "800.00MHz");
+        assert_eq!(format_freq(1000000.), "1.00GHz");
+        assert_eq!(format_freq(1000.), "1.00MHz");
+    }
+}
@@ -0,0 +1,334 @@
+//! Metrics showing the state of the i3 (or sway) window manager: the focused window's title, the
+//! current binding mode and the number of windows on the scratchpad. They are kept up to date by
+//! a thread subscribed to i3's events, which triggers a render whenever something changes.
+
+use framework::*;
+use helper::{format_template, truncate};
+use i3ipc;
+use i3ipc::Connection;
+use json::Json;
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_TITLE_LEN: usize = 60;
+const DEFAULT_SCRATCHPAD_FMT: &'static str = "scratchpad: %n";
+/// Time to wait before reconnecting after i3 went away (e.g. on a restart), in milliseconds.
+const RECONNECT_DELAY: u64 = 1000;
+
+struct I3State {
+    /// None while the first connection attempt is in progress.
+    connected: Option<bool>,
+    title: String,
+    mode: String,
+    scratchpad: usize,
+}
+
+/// Updates the state from an event, or initially (event is None) from scratch. query is a
+/// separate connection for requests.
+type Handler = fn(&mut Connection, Option<(u32, &Json)>, &mut I3State) -> Result<(), String>;
+
+fn str_member<'a>(v: &'a Json, key: &str) -> &'a str {
+    v.get(key).and_then(Json::as_str).unwrap_or("")
+}
+
+fn children<'a>(node: &'a Json) -> Vec<&'a Json> {
+    let mut children = Vec::new();
+    for key in ["nodes", "floating_nodes"].iter() {
+        if let Some(nodes) = node.get(key).and_then(Json::as_array) {
+            children.extend(nodes.iter());
+        }
+    }
+    children
+}
+
+/// Returns the title of the focused window in a layout tree as returned by GET_TREE; empty if
+/// an empty workspace is focused.
+fn focused_title(node: &Json) -> Option<String> {
+    if node.get("focused").and_then(Json::as_bool) == Some(true) {
+        return match str_member(node, "type") {
+            "workspace" | "output" | "root" => Some(String::new()),
+            _ => Some(String::from(str_member(node, "name"))),
+        };
+    }
+    children(node).into_iter().filter_map(focused_title).next()
+}
+
+fn count_leaves(node: &Json) -> usize {
+    let children = children(node);
+    if children.is_empty() {
+        1
+    } else {
+        children.into_iter().map(count_leaves).sum()
+    }
+}
+
+/// Returns the number of windows on the scratchpad, i.e. the hidden workspace "__i3_scratch".
+fn scratchpad_count(node: &Json) -> usize {
+    if str_member(node, "name") == "__i3_scratch" {
+        return children(node).into_iter().map(count_leaves).sum();
+    }
+    children(node).into_iter().map(scratchpad_count).sum()
+}
+
+fn handle_title(query: &mut Connection,
+                event: Option<(u32, &Json)>,
+                st: &mut I3State)
+                -> Result<(), String> {
+    if let Some((i3ipc::EVENT_WINDOW, ev)) = event {
+        let container = ev.get("container").unwrap_or(&Json::Null);
+        let focused = container.get("focused").and_then(Json::as_bool) == Some(true);
+
+        match str_member(ev, "change") {
+            "focus" => st.title = String::from(str_member(container, "name")),
+            "title" if focused => st.title = String::from(str_member(container, "name")),
+            "title" | "new" | "mark" | "urgent" => (),
+            _ => st.title = focused_title(&query.request(i3ipc::GET_TREE, "")?)
+                                .unwrap_or(String::new()),
+        }
+        return Ok(());
+    }
+    // Initially, and on workspace changes (a workspace without windows may have been focused).
+    st.title = focused_title(&query.request(i3ipc::GET_TREE, "")?).unwrap_or(String::new());
+    Ok(())
+}
+
+fn handle_mode(query: &mut Connection,
+               event: Option<(u32, &Json)>,
+               st: &mut I3State)
+               -> Result<(), String> {
+    st.mode = match event {
+        Some((_, ev)) => String::from(str_member(ev, "change")),
+        // GET_BINDING_STATE needs i3 4.19; older versions start in the default mode anyway.
+        None => {
+            query.request(i3ipc::GET_BINDING_STATE, "")
+                 .ok()
+                 .and_then(|reply| reply.get("name").and_then(Json::as_str).map(String::from))
+                 .unwrap_or(String::from("default"))
+        }
+    };
+    Ok(())
+}
+
+fn handle_scratchpad(query: &mut Connection,
+                     event: Option<(u32, &Json)>,
+                     st: &mut I3State)
+                     -> Result<(), String> {
+    if let Some((_, ev)) = event {
+        if str_member(ev, "change") == "title" {
+            return Ok(());
+        }
+    }
+    st.scratchpad = scratchpad_count(&query.request(i3ipc::GET_TREE, "")?);
+    Ok(())
+}
+
+/// Handles one connection to i3 until it fails, or until the metric has been dropped.
+fn session(path: &str,
+           events: &[&str],
+           handler: Handler,
+           state: &Arc<Mutex<I3State>>,
+           notifier: &Option<Notifier>)
+           -> Result<(), String> {
+    let notify = || if let Some(ref n) = *notifier {
+        n.notify();
+    };
+
+    let mut query = Connection::connect(path)?;
+    let mut sub = Connection::connect(path)?;
+    // Subscribe first so that no change between the initial query and the subscription is lost.
+    sub.subscribe(events)?;
+    {
+        let mut st = state.lock().unwrap();
+        handler(&mut query, None, &mut st)?;
+        st.connected = Some(true);
+    }
+    notify();
+
+    loop {
+        let (msg_type, ev) = sub.recv()?;
+        if Arc::strong_count(state) == 1 {
+            return Ok(());
+        }
+        if msg_type == i3ipc::EVENT_SHUTDOWN {
+            return Err(String::from("i3 is shutting down"));
+        }
+        handler(&mut query, Some((msg_type, &ev)), &mut state.lock().unwrap())?;
+        notify();
+    }
+}
+
+/// Keeps the state up to date, reconnecting whenever the connection is lost. Returns once the
+/// metric holding the other reference to state has been dropped.
+fn watch(path: Option<String>,
+         events: &'static [&'static str],
+         handler: Handler,
+         state: Arc<Mutex<I3State>>,
+         notifier: Option<Notifier>) {
+    while Arc::strong_count(&state) > 1 {
+        let result = match path.clone().or_else(i3ipc::socket_path) {
+            None => Err(String::from("no i3 socket found")),
+            Some(p) => session(&p, events, handler, &state, &notifier),
+        };
+        if result.is_err() {
+            state.lock().unwrap().connected = Some(false);
+            if let Some(ref n) = notifier {
+                n.notify();
+            }
+        }
+        thread::sleep(Duration::from_millis(RECONNECT_DELAY));
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Title,
+    Mode,
+    Scratchpad,
+}
+
+struct I3Metric {
+    kind: Kind,
+    state: Arc<Mutex<I3State>>,
+    /// Maximum length of the title in characters.
+    max_len: usize,
+    fmt: String,
+}
+
+impl I3Metric {
+    fn new(kind: Kind) -> I3Metric {
+        I3Metric {
+            kind: kind,
+            state: Arc::new(Mutex::new(I3State {
+                connected: None,
+                title: String::new(),
+                mode: String::from("default"),
+                scratchpad: 0,
+            })),
+            max_len: DEFAULT_TITLE_LEN,
+            fmt: String::from(DEFAULT_SCRATCHPAD_FMT),
+        }
+    }
+
+    fn start(&self, path: Option<String>, notifier: Option<Notifier>) {
+        let (events, handler): (&'static [&'static str], Handler) = match self.kind {
+            Kind::Title => (&["window", "workspace"], handle_title),
+            Kind::Mode => (&["mode"], handle_mode),
+            Kind::Scratchpad => (&["window"], handle_scratchpad),
+        };
+        let state = self.state.clone();
+        thread::spawn(move || watch(path, events, handler, state, notifier));
+    }
+}
+
+impl Metric for I3Metric {
+    // arg is the maximum length of the window title (0 for no limit), or the format of the
+    // scratchpad count (%n).
+    fn init(&mut self, st: &mut MetricState, arg: Option<String>) {
+        match (self.kind, arg) {
+            (Kind::Title, Some(arg)) => {
+                self.max_len = usize::from_str(arg.trim()).unwrap_or(DEFAULT_TITLE_LEN)
+            }
+            (Kind::Scratchpad, Some(arg)) => self.fmt = arg,
+            _ => (),
+        }
+        self.start(i3ipc::socket_path(), st.notifier());
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        let st = self.state.lock().unwrap();
+
+        match (st.connected, self.kind) {
+            (None, _) => RenderResult::new(String::new(), Color::Default),
+            (Some(false), _) => RenderResult::new(String::from("i3 n/a"), Color::Red),
+            (Some(true), Kind::Title) => {
+                RenderResult::new(truncate(&st.title, self.max_len), Color::Default)
+            }
+            // i3bar hides blocks with an empty text.
+            (Some(true), Kind::Mode) if st.mode == "default" => {
+                RenderResult::new(String::new(), Color::Default)
+            }
+            (Some(true), Kind::Mode) => RenderResult::new(st.mode.clone(), Color::Red),
+            (Some(true), Kind::Scratchpad) => {
+                let mut values = BTreeMap::new();
+                values.insert('n', format!("{}", st.scratchpad));
+                RenderResult::new(format_template(&self.fmt, &values), Color::Default)
+            }
+        }
+    }
+}
+
+pub fn make_window_title_metric() -> Box<Metric> {
+    Box::new(I3Metric::new(Kind::Title))
+}
+
+pub fn make_binding_mode_metric() -> Box<Metric> {
+    Box::new(I3Metric::new(Kind::Mode))
+}
+
+pub fn make_scratchpad_metric() -> Box<Metric> {
+    Box::new(I3Metric::new(Kind::Scratchpad))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use i3ipc::tests::{listen, read_message, write_message};
+    use json;
+
+    use std::sync::mpsc::channel;
+
+    const TREE: &'static str = r#"{"type":"root","focused":false,"nodes":[
+        {"name":"__i3","type":"output","nodes":[{"name":"content","type":"con","nodes":[
+            {"name":"__i3_scratch","type":"workspace","nodes":[],"floating_nodes":[
+                {"type":"floating_con","nodes":[{"name":"notes","type":"con","nodes":[]}]},
+                {"type":"floating_con","nodes":[{"name":"music","type":"con","nodes":[]}]}]}]}]},
+        {"name":"eDP-1","type":"output","nodes":[{"name":"content","type":"con","nodes":[
+            {"name":"1","type":"workspace","focused":false,"nodes":[
+                {"name":"vim \"notes\"","type":"con","focused":true,"nodes":[]},
+                {"name":"firefox","type":"con","focused":false,"nodes":[]}]}]}]}]}"#;
+
+    #[test]
+    fn test_tree() {
+        let tree = json::parse(TREE).unwrap();
+        assert_eq!(focused_title(&tree), Some(String::from("vim \"notes\"")));
+        assert_eq!(scratchpad_count(&tree), 2);
+    }
+
+    #[test]
+    fn test_binding_mode_updates() {
+        let (listener, path) = listen("mode");
+        let (done_tx, done_rx) = channel::<()>();
+        let server = thread::spawn(move || {
+            let (mut query, _) = listener.accept().unwrap();
+            let (mut sub, _) = listener.accept().unwrap();
+
+            assert_eq!(read_message(&mut sub).map(|m| m.0), Some(i3ipc::SUBSCRIBE));
+            write_message(&mut sub, i3ipc::SUBSCRIBE, r#"{"success":true}"#);
+            assert_eq!(read_message(&mut query).map(|m| m.0),
+                       Some(i3ipc::GET_BINDING_STATE));
+            write_message(&mut query, i3ipc::GET_BINDING_STATE, r#"{"name":"default"}"#);
+            write_message(&mut sub, i3ipc::EVENT_MODE, r#"{"change":"resize"}"#);
+            // Keep the connections open until the client has seen the event.
+            let _ = done_rx.recv();
+        });
+
+        let (tx, rx) = channel();
+        let mut st = MetricState::with_wakeups(tx);
+        let mut metric = I3Metric::new(Kind::Mode);
+        metric.start(Some(path.clone()), st.notifier());
+
+        // One wakeup after connecting, one for the event.
+        for _ in 0..2 {
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(metric.render(&mut st).get().0, "resize");
+
+        drop(done_tx);
+        server.join().unwrap();
+        let _ = ::std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,190 @@
+//! Shows IP addresses and link state of network interfaces.
+
+use framework::*;
+use helper::{commaseparated_to_vec, read_procfs_file, read_sysfs_file};
+
+extern crate libc;
+
+use std::ffi::CStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ptr;
+
+/// Pseudo interface name that selects the interface holding the default route.
+const DEFAULT_ROUTE_IF: &'static str = "default";
+
+struct IPAddrMetric {
+    interfaces: Vec<String>,
+    hide_link_local: bool,
+}
+
+/// Returns the interface of the IPv4 default route with the lowest metric from the contents of
+/// /proc/net/route:
+///
+///     Iface  Destination  Gateway   Flags  RefCnt  Use  Metric  Mask      MTU  Window  IRTT
+///     eth0   00000000     0101A8C0  0003   0       0    100     00000000  0    0       0
+fn parse_default_route(content: &str) -> Option<String> {
+    const RTF_UP: u32 = 0x1;
+    let mut best: Option<(u32, String)> = None;
+
+    for line in content.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 8 {
+            continue;
+        }
+
+        let flags = u32::from_str_radix(fields[3], 16).unwrap_or(0);
+        let metric = fields[6].parse::<u32>().unwrap_or(u32::max_value());
+        if fields[1] != "00000000" || fields[7] != "00000000" || flags & RTF_UP == 0 {
+            continue;
+        }
+        if best.as_ref().map_or(true, |&(m, _)| metric < m) {
+            best = Some((metric, String::from(fields[0])));
+        }
+    }
+    best.map(|(_, interface)| interface)
+}
+
+/// Returns all IPv4 and IPv6 addresses as (interface, address), using getifaddrs(3).
+fn get_addresses() -> Vec<(String, IpAddr)> {
+    let mut addrs = Vec::new();
+    let mut ifap: *mut libc::ifaddrs = ptr::null_mut();
+
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return addrs;
+    }
+
+    let mut cur = ifap;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+
+        let family = unsafe { (*ifa.ifa_addr).sa_family } as libc::c_int;
+        let addr = if family == libc::AF_INET {
+            let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+            IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+        } else if family == libc::AF_INET6 {
+            let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+            IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+        } else {
+            continue;
+        };
+
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }.to_string_lossy().into_owned();
+        addrs.push((name, addr));
+    }
+
+    unsafe { libc::freeifaddrs(ifap) };
+    addrs
+}
+
+fn is_link_local(addr: &IpAddr) -> bool {
+    match *addr {
+        IpAddr::V4(ref a) => a.is_link_local(),
+        IpAddr::V6(ref a) => a.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+impl IPAddrMetric {
+    /// Returns true if the interface is up and has a carrier.
+    fn link_up(interface: &str) -> bool {
+        let operstate = read_sysfs_file(format!("/class/net/{}/operstate", interface));
+        let carrier = read_sysfs_file(format!("/class/net/{}/carrier", interface));
+
+        // Some virtual interfaces (e.g. tun devices) report "unknown" even if they work.
+        let up = match operstate {
+            Some(ref s) => s == "up" || s == "unknown",
+            None => false,
+        };
+        up && carrier.map_or(false, |c| c == "1")
+    }
+
+    /// Resolves the pseudo interface name "default".
+    fn resolve_interfaces(&self) -> Vec<String> {
+        self.interfaces
+            .iter()
+            .filter_map(|i| {
+                if i == DEFAULT_ROUTE_IF {
+                    read_procfs_file(String::from("/net/route"))
+                        .and_then(|r| parse_default_route(&r))
+                } else {
+                    Some(i.clone())
+                }
+            })
+            .collect()
+    }
+}
+
+impl Metric for IPAddrMetric {
+    // arg is a comma-separated list of interfaces, where "default" stands for the interface of the
+    // default route, optionally followed by "nolinklocal": "default,wlan0 nolinklocal"
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        let arg = arg.unwrap_or(String::from(DEFAULT_ROUTE_IF));
+        let mut parts = arg.split_whitespace();
+
+        self.interfaces = commaseparated_to_vec(String::from(parts.next()
+                                                                  .unwrap_or(DEFAULT_ROUTE_IF)));
+        for opt in parts {
+            if opt == "nolinklocal" {
+                self.hide_link_local = true;
+            }
+        }
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        let interfaces = self.resolve_interfaces();
+        if interfaces.is_empty() {
+            return RenderResult::new(String::from("no route"), Color::Red);
+        }
+
+        let addrs = get_addresses();
+        let mut color = Color::Green;
+        let mut out = Vec::new();
+
+        for interface in interfaces {
+            if !IPAddrMetric::link_up(&interface) {
+                color = Color::Red;
+                out.push(format!("{}: down", interface));
+                continue;
+            }
+
+            let mut line = format!("{}:", interface);
+            for &(_, ref addr) in addrs.iter().filter(|&&(ref i, _)| *i == interface) {
+                if self.hide_link_local && is_link_local(addr) {
+                    continue;
+                }
+                line.push_str(&format!(" {}", addr));
+            }
+            out.push(line);
+        }
+
+        RenderResult::new(out.join(" "), color)
+    }
+}
+
+pub fn make_ipaddr_metric() -> Box<Metric> {
+    Box::new(IPAddrMetric {
+        interfaces: Vec::new(),
+        hide_link_local: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_default_route;
+
+    const PROC_NET_ROUTE: &'static str = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0000000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+
+    #[test]
+    fn test_parse_default_route() {
+        assert_eq!(parse_default_route(PROC_NET_ROUTE), Some(String::from("eth0")));
+        assert_eq!(parse_default_route(""), None);
+    }
+}
@@ -0,0 +1,426 @@
+//! Shows the active keyboard layout and the lock keys that are switched on. On sway, the layout
+//! comes from the IPC input events; on X11, the active group from the XKB extension and its name
+//! from setxkbmap. The lock states are read from the keyboard LEDs in /sys/class/leds.
+
+extern crate libc;
+
+use framework::*;
+use helper::{format_template, run_command};
+use i3ipc;
+use i3ipc::Connection;
+use json::Json;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_FMT: &'static str = "%l %k";
+/// Time to wait before reconnecting after sway went away, in milliseconds.
+const RECONNECT_DELAY: u64 = 1000;
+/// Minimum time between two runs of setxkbmap on X11, in milliseconds; the configured layouts
+/// rarely change, unlike the active one.
+const X11_LAYOUTS_REFRESH_INTERVAL: i64 = 60000;
+/// XkbUseCoreKbd from X11/extensions/XKB.h.
+const XKB_USE_CORE_KBD: libc::c_uint = 0x0100;
+
+/// Lock keys: name used in the locks= option, LED name suffix, label and placeholder.
+const LOCKS: [(&'static str, &'static str, &'static str, char); 3] =
+    [("caps", "capslock", "CAPS", 'c'),
+     ("num", "numlock", "NUM", 'n'),
+     ("scroll", "scrolllock", "SCROLL", 's')];
+
+/// Returns whether any LED in dir (normally /sys/class/leds) of the given kind, e.g.
+/// "input3::capslock", is on. There is one per keyboard.
+fn led_on(dir: &str, kind: &str) -> bool {
+    let suffix = format!("::{}", kind);
+    let entries = match fs::read_dir(dir) {
+        Err(_) => return false,
+        Ok(e) => e,
+    };
+    entries.filter_map(|e| e.ok())
+           .filter_map(|e| e.file_name().into_string().ok())
+           .filter(|name| name.ends_with(&suffix))
+           .any(|name| {
+               fs::read_to_string(format!("{}/{}/brightness", dir, name))
+                   .map(|b| b.trim() != "0")
+                   .unwrap_or(false)
+           })
+}
+
+/// Returns the active layout of a keyboard from an entry of sway's GET_INPUTS reply or an input
+/// event. keyboard restricts it to the device with this identifier.
+fn keyboard_layout(input: &Json, keyboard: &Option<String>) -> Option<String> {
+    if input.get("type").and_then(Json::as_str) != Some("keyboard") {
+        return None;
+    }
+    if let Some(ref id) = *keyboard {
+        if input.get("identifier").and_then(Json::as_str) != Some(id) {
+            return None;
+        }
+    }
+    input.get("xkb_active_layout_name").and_then(Json::as_str).map(String::from)
+}
+
+/// Mirrors XkbStateRec from X11/XKBlib.h.
+#[repr(C)]
+#[derive(Default)]
+struct XkbState {
+    group: u8,
+    locked_group: u8,
+    base_group: u16,
+    latched_group: u16,
+    mods: u8,
+    base_mods: u8,
+    latched_mods: u8,
+    locked_mods: u8,
+    compat_state: u8,
+    grab_mods: u8,
+    compat_grab_mods: u8,
+    lookup_mods: u8,
+    compat_lookup_mods: u8,
+    ptr_buttons: u16,
+}
+
+type XOpenDisplayFn = unsafe extern "C" fn(*const libc::c_char) -> *mut libc::c_void;
+type XkbGetStateFn = unsafe extern "C" fn(*mut libc::c_void, libc::c_uint, *mut XkbState)
+                                          -> libc::c_int;
+
+/// A connection to the X server. libX11 is loaded at runtime, so that it's only needed on X11.
+struct XDisplay {
+    display: *mut libc::c_void,
+    get_state: XkbGetStateFn,
+}
+
+impl XDisplay {
+    /// Connects to $DISPLAY; None if libX11 or the display isn't available.
+    fn open() -> Option<XDisplay> {
+        let sym = |lib, name: &[u8]| unsafe { libc::dlsym(lib, name.as_ptr() as *const _) };
+        unsafe {
+            let lib = libc::dlopen(b"libX11.so.6\0".as_ptr() as *const _,
+                                   libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if lib.is_null() {
+                return None;
+            }
+            let (open, get_state) = (sym(lib, b"XOpenDisplay\0"), sym(lib, b"XkbGetState\0"));
+            if open.is_null() || get_state.is_null() {
+                return None;
+            }
+            let display = mem::transmute::<_, XOpenDisplayFn>(open)(ptr::null());
+            if display.is_null() {
+                return None;
+            }
+            Some(XDisplay {
+                display: display,
+                get_state: mem::transmute(get_state),
+            })
+        }
+    }
+
+    /// Returns the index of the active group, i.e. layout.
+    fn group(&self) -> Option<usize> {
+        let mut state = XkbState::default();
+        match unsafe { (self.get_state)(self.display, XKB_USE_CORE_KBD, &mut state) } {
+            0 => Some(state.group as usize),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the configured layouts, e.g. ["us", "de"], from the output of "setxkbmap -query".
+fn parse_x11_layouts(setxkbmap: &str) -> Vec<String> {
+    setxkbmap.lines()
+             .find(|l| l.starts_with("layout:"))
+             .map(|l| l[7..].trim().split(',').map(String::from).collect())
+             .unwrap_or(Vec::new())
+}
+
+/// The layout on X11: the configured layouts and the active group.
+struct X11Layout {
+    display: Option<XDisplay>,
+    layouts: Vec<String>,
+    layouts_queried: i64,
+}
+
+impl X11Layout {
+    fn new() -> X11Layout {
+        X11Layout {
+            display: XDisplay::open(),
+            layouts: Vec::new(),
+            layouts_queried: 0,
+        }
+    }
+
+    fn layout(&mut self) -> Option<String> {
+        let group = self.display.as_ref()?.group()?;
+        let now = MetricState::now();
+        if self.layouts.is_empty() || now - self.layouts_queried >= X11_LAYOUTS_REFRESH_INTERVAL {
+            self.layouts = parse_x11_layouts(&run_command("setxkbmap", &["-query"])
+                                                  .unwrap_or(String::new()));
+            self.layouts_queried = now;
+        }
+        self.layouts.get(group).or(self.layouts.first()).cloned()
+    }
+}
+
+struct LayoutState {
+    /// None while the first connection attempt is in progress.
+    connected: Option<bool>,
+    layout: Option<String>,
+}
+
+/// Follows sway's input events until the connection fails, or until the metric has been dropped.
+fn session(path: &str,
+           keyboard: &Option<String>,
+           state: &Arc<Mutex<LayoutState>>,
+           notifier: &Option<Notifier>)
+           -> Result<(), String> {
+    let notify = || if let Some(ref n) = *notifier {
+        n.notify();
+    };
+
+    let mut sub = Connection::connect(path)?;
+    sub.subscribe(&["input"])?;
+    let inputs = Connection::connect(path)?.request(i3ipc::GET_INPUTS, "")?;
+    {
+        let mut st = state.lock().unwrap();
+        st.layout = inputs.as_array().and_then(|inputs| {
+            inputs.iter().filter_map(|i| keyboard_layout(i, keyboard)).next()
+        });
+        st.connected = Some(true);
+    }
+    notify();
+
+    loop {
+        let (msg_type, ev) = sub.recv()?;
+        if Arc::strong_count(state) == 1 {
+            return Ok(());
+        }
+        if msg_type == i3ipc::EVENT_SHUTDOWN {
+            return Err(String::from("sway is shutting down"));
+        }
+        if msg_type != i3ipc::EVENT_INPUT {
+            continue;
+        }
+        // With several keyboards, the one whose layout changed last wins.
+        if let Some(layout) = ev.get("input").and_then(|i| keyboard_layout(i, keyboard)) {
+            state.lock().unwrap().layout = Some(layout);
+            notify();
+        }
+    }
+}
+
+fn watch(path: String,
+         keyboard: Option<String>,
+         state: Arc<Mutex<LayoutState>>,
+         notifier: Option<Notifier>) {
+    while Arc::strong_count(&state) > 1 {
+        if session(&path, &keyboard, &state, &notifier).is_err() {
+            state.lock().unwrap().connected = Some(false);
+            if let Some(ref n) = notifier {
+                n.notify();
+            }
+        }
+        thread::sleep(Duration::from_millis(RECONNECT_DELAY));
+    }
+}
+
+struct KbdMetric {
+    fmt: String,
+    /// Lock keys to show, as indices into LOCKS.
+    locks: Vec<usize>,
+    keyboard: Option<String>,
+    /// Set on sway.
+    sway: Option<Arc<Mutex<LayoutState>>>,
+    /// Set otherwise, i.e. on X11.
+    x11: Option<X11Layout>,
+    leds_dir: String,
+    error: Option<String>,
+}
+
+impl KbdMetric {
+    fn configure(&mut self, arg: &str) -> Result<(), String> {
+        let mut parts = arg.splitn(2, ';');
+
+        for word in parts.next().unwrap_or("").split_whitespace() {
+            let mut kv = word.splitn(2, '=');
+            match (kv.next().unwrap_or(""), kv.next()) {
+                ("keyboard", Some(id)) => self.keyboard = Some(String::from(id)),
+                ("locks", Some(names)) => {
+                    self.locks.clear();
+                    for name in names.split(',') {
+                        match LOCKS.iter().position(|l| l.0 == name) {
+                            Some(i) => self.locks.push(i),
+                            None => return Err(format!("unknown lock '{}'", name)),
+                        }
+                    }
+                }
+                _ => return Err(format!("unknown option '{}'", word)),
+            }
+        }
+        if let Some(fmt) = parts.next() {
+            self.fmt = String::from(fmt);
+        }
+        Ok(())
+    }
+
+    /// Returns the layout, or None if it can't be determined.
+    fn layout(&mut self) -> Option<String> {
+        if let Some(ref mut x11) = self.x11 {
+            return x11.layout();
+        }
+        match self.sway {
+            None => None,
+            Some(ref state) => {
+                let st = state.lock().unwrap();
+                match st.connected {
+                    None => Some(String::new()),
+                    Some(false) => None,
+                    Some(true) => st.layout.clone(),
+                }
+            }
+        }
+    }
+}
+
+impl Metric for KbdMetric {
+    // arg consists of options: "keyboard=ID" (sway input identifier, see swaymsg -t get_inputs)
+    // and "locks=caps,num,scroll" (the lock keys to show); optionally followed by ';' and a
+    // format with %l for the layout, %k for the active locks, and %c, %n, %s for each lock
+    // (empty if off): "locks=caps;⌨ %l %k"
+    fn init(&mut self, st: &mut MetricState, arg: Option<String>) {
+        if let Err(e) = self.configure(&arg.unwrap_or(String::new())) {
+            self.error = Some(format!("kbd: {}", e));
+            return;
+        }
+        // Only sway has GET_INPUTS; i3 doesn't even reply to unknown requests.
+        if let Some(path) = env::var("SWAYSOCK").ok().filter(|p| !p.is_empty()) {
+            let state = Arc::new(Mutex::new(LayoutState {
+                connected: None,
+                layout: None,
+            }));
+            let (keyboard, thread_state) = (self.keyboard.clone(), state.clone());
+            let notifier = st.notifier();
+            thread::spawn(move || watch(path, keyboard, thread_state, notifier));
+            self.sway = Some(state);
+        } else {
+            self.x11 = Some(X11Layout::new());
+        }
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
+        let layout = match self.layout() {
+            None => return RenderResult::new(String::from("kbd n/a"), Color::Red),
+            Some(l) => l,
+        };
+
+        let mut values = BTreeMap::new();
+        let mut active = Vec::new();
+        let mut caps = false;
+        for (i, &(_, led, label, placeholder)) in LOCKS.iter().enumerate() {
+            let on = self.locks.contains(&i) && led_on(&self.leds_dir, led);
+            if on {
+                active.push(label);
+                caps |= led == "capslock";
+            }
+            values.insert(placeholder, String::from(if on { label } else { "" }));
+        }
+        values.insert('l', layout);
+        values.insert('k', active.join(" "));
+
+        let text = String::from(format_template(&self.fmt, &values).trim());
+        // Caps lock is the one that gets people into trouble.
+        if caps {
+            RenderResult::new(text, Color::Red).with_urgent(true)
+        } else {
+            RenderResult::new(text, Color::Default)
+        }
+    }
+}
+
+pub fn make_kbd_metric() -> Box<Metric> {
+    Box::new(KbdMetric {
+        fmt: String::from(DEFAULT_FMT),
+        locks: (0..LOCKS.len()).collect(),
+        keyboard: None,
+        sway: None,
+        x11: None,
+        leds_dir: String::from("/sys/class/leds"),
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json;
+
+    #[test]
+    fn test_layouts() {
+        let inputs = json::parse(r#"[
+            {"identifier":"1:1:AT_Translated_Set_2_keyboard","type":"keyboard",
+             "xkb_active_layout_name":"German"},
+            {"identifier":"1267:12608:MSFT0001:01_04F3:3140_Touchpad","type":"touchpad"},
+            {"identifier":"1452:591:Apple_Keyboard","type":"keyboard",
+             "xkb_active_layout_name":"English (US)"}]"#)
+                         .unwrap();
+        let inputs = inputs.as_array().unwrap();
+        let apple = Some(String::from("1452:591:Apple_Keyboard"));
+        assert_eq!(keyboard_layout(&inputs[0], &None), Some(String::from("German")));
+        assert_eq!(keyboard_layout(&inputs[0], &apple), None);
+        assert_eq!(keyboard_layout(&inputs[1], &None), None);
+        assert_eq!(keyboard_layout(&inputs[2], &apple), Some(String::from("English (US)")));
+
+        let setxkbmap = "rules:      evdev\nmodel:      pc105\nlayout:     us,de,ua\n";
+        assert_eq!(parse_x11_layouts(setxkbmap), vec!["us", "de", "ua"]);
+        assert!(parse_x11_layouts("rules: evdev\n").is_empty());
+    }
+
+    #[test]
+    fn test_locks() {
+        let dir = format!("{}/myi3stat-test-leds-{}",
+                          env::temp_dir().display(),
+                          ::std::process::id());
+        for &(led, on) in [("input3::capslock", "1"),
+                           ("input3::numlock", "0"),
+                           ("input9::numlock", "0"),
+                           ("tpacpi::power", "1")]
+                              .iter() {
+            fs::create_dir_all(format!("{}/{}", dir, led)).unwrap();
+            fs::write(format!("{}/{}/brightness", dir, led), format!("{}\n", on)).unwrap();
+        }
+
+        let mut metric = KbdMetric {
+            fmt: String::from("[%c|%n] %k"),
+            locks: vec![0, 1],
+            keyboard: None,
+            sway: Some(Arc::new(Mutex::new(LayoutState {
+                connected: Some(true),
+                layout: Some(String::from("German")),
+            }))),
+            x11: None,
+            leds_dir: dir.clone(),
+            error: None,
+        };
+        let mut st = MetricState::new();
+        let result = metric.render(&mut st);
+        assert_eq!(result.get().0, "[CAPS|] CAPS");
+        assert!(result.is_urgent());
+
+        fs::write(format!("{}/input3::capslock/brightness", dir), "0\n").unwrap();
+        fs::write(format!("{}/input9::numlock/brightness", dir), "1\n").unwrap();
+        metric.fmt = String::from(DEFAULT_FMT);
+        let result = metric.render(&mut st);
+        assert_eq!(result.get().0, "German NUM");
+        assert!(!result.is_urgent());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -3,38 +3,125 @@ extern crate regex;
 use self::regex::Regex;
 
 use framework::*;
-use helper::read_procfs_file;
+use helper::{commaseparated_to_vec, number_of_cores, read_procfs_file};
 use helper::extract_from_str;
 
-struct LoadAvg;
+/// Contents of /proc/loadavg.
+struct LoadInfo {
+    /// Load averages over 1, 5 and 15 minutes.
+    loads: [f64; 3],
+    running: u64,
+    total: u64,
+    last_pid: u64,
+}
+
+struct LoadAvg {
+    /// Indices into LoadInfo::loads of the averages to show.
+    shown: Vec<usize>,
+    /// If set, loads are divided by the number of cores.
+    normalize: bool,
+    show_tasks: bool,
+    show_last_pid: bool,
+    ncpu: i32,
+    re: Regex,
+    error: Option<String>,
+}
 
 impl LoadAvg {
-    fn read_load_avg() -> (String, Color) {
-        let loads = read_procfs_file(String::from("/loadavg"))
-                        .unwrap_or(String::from("0.0 0.0 0.0  "));
-        let re = Regex::new(r"([0-9\.]+)\s+([0-9\.]+)\s+([0-9\.]+).*").unwrap();
-        let load_avgs: Vec<f64> = extract_from_str(&loads, &re, 0.);
-
-        if load_avgs.len() < 3 {
-            (String::from("0.0 0.0 0.0"), Color::Purple)
+    fn new() -> LoadAvg {
+        LoadAvg {
+            shown: vec![0, 1, 2],
+            normalize: false,
+            show_tasks: false,
+            show_last_pid: false,
+            ncpu: 1,
+            // loads, runnable/total tasks, last PID: "0.20 0.18 0.12 1/80 11206"
+            re: Regex::new(r"([0-9\.]+)\s+([0-9\.]+)\s+([0-9\.]+)\s+(\d+)/(\d+)\s+(\d+)").unwrap(),
+            error: None,
+        }
+    }
+
+    fn configure(&mut self, arg: &str) -> Result<(), String> {
+        let mut averages = None;
+
+        for word in arg.split_whitespace() {
+            match word {
+                "norm" => self.normalize = true,
+                "tasks" => self.show_tasks = true,
+                "pid" => self.show_last_pid = true,
+                avgs => {
+                    if averages.is_some() {
+                        return Err(format!("averages given twice: '{}'", avgs));
+                    }
+                    let shown = commaseparated_to_vec(String::from(avgs))
+                                    .iter()
+                                    .map(|a| {
+                                        match a.as_str() {
+                                            "1" => Ok(0),
+                                            "5" => Ok(1),
+                                            "15" => Ok(2),
+                                            _ => Err(format!("unknown option '{}'", word)),
+                                        }
+                                    })
+                                    .collect::<Result<Vec<usize>, String>>()?;
+                    averages = Some(shown);
+                }
+            }
+        }
+        if let Some(shown) = averages {
+            self.shown = shown;
+        }
+        Ok(())
+    }
+
+    fn read_load_avg(&self) -> Option<LoadInfo> {
+        let loads = read_procfs_file(String::from("/loadavg")).unwrap_or(String::new());
+        self.parse_load_avg(&loads)
+    }
+
+    fn parse_load_avg(&self, loads: &String) -> Option<LoadInfo> {
+        let fields: Vec<f64> = extract_from_str(loads, &self.re, 0.);
+
+        if fields.len() < 6 {
+            None
         } else {
-            (format!("{:5.2} {:5.2} {:5.2}",
-                     load_avgs[0],
-                     load_avgs[1],
-                     load_avgs[2]),
-             LoadAvg::get_color(load_avgs[0]))
+            Some(LoadInfo {
+                loads: [fields[0], fields[1], fields[2]],
+                running: fields[3] as u64,
+                total: fields[4] as u64,
+                last_pid: fields[5] as u64,
+            })
         }
     }
 
+    /// Formats the selected loads and fields of /proc/loadavg.
+    fn render_info(&self, info: &LoadInfo) -> RenderResult {
+        let divisor = if self.normalize { self.ncpu as f64 } else { 1. };
+        let loads: Vec<f64> = self.shown.iter().map(|&i| info.loads[i] / divisor).collect();
+
+        let mut parts: Vec<String> = loads.iter().map(|l| format!("{:5.2}", l)).collect();
+        if self.show_tasks {
+            parts.push(format!("{}/{}", info.running, info.total));
+        }
+        if self.show_last_pid {
+            parts.push(format!("{}", info.last_pid));
+        }
+
+        RenderResult::new(parts.join(" "),
+                          self.get_color(loads.first().cloned().unwrap_or(0.)))
+    }
+
     // load is a string containing one float number.
-    fn get_color(f: f64) -> Color {
+    fn get_color(&self, f: f64) -> Color {
+        // Normalized loads are per core, so they need lower thresholds.
+        let (warn, crit) = if self.normalize { (0.7, 1.) } else { (1.5, 3.) };
 
         // TODO: Make color thresholds configurable
-        if f >= 0. && f < 1.5 {
+        if f >= 0. && f < warn {
             Color::Green
-        } else if f >= 1.5 && f < 3. {
+        } else if f >= warn && f < crit {
             Color::Orange
-        } else if f >= 3. {
+        } else if f >= crit {
             Color::Red
         } else {
             Color::Default
@@ -43,12 +130,82 @@ impl LoadAvg {
 }
 
 impl Metric for LoadAvg {
+    // arg is a comma-separated list of the averages to show (1, 5, 15), optionally followed by
+    // options: "norm" divides loads by the number of cores, "tasks" shows runnable/total tasks,
+    // "pid" the last PID. Example: "1,15 norm tasks"
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        if let Err(e) = self.configure(&arg.unwrap_or(String::new())) {
+            self.error = Some(format!("load: {}", e));
+            return;
+        }
+        if self.normalize {
+            self.ncpu = number_of_cores();
+        }
+    }
+
     fn render(&mut self, _: &mut MetricState) -> RenderResult {
-        let (loads, color) = LoadAvg::read_load_avg();
-        RenderResult::new(loads, color)
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
+        match self.read_load_avg() {
+            None => RenderResult::new(String::from("0.0 0.0 0.0"), Color::Purple),
+            Some(info) => self.render_info(&info),
+        }
     }
 }
 
 pub fn make_load_metric() -> Box<Metric> {
-    Box::new(LoadAvg)
+    Box::new(LoadAvg::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOADAVG: &'static str = "3.20 1.60 0.80 5/812 31337\n";
+
+    #[test]
+    fn test_render() {
+        let mut st = MetricState::new();
+        let mut render = |arg: &str| {
+            let mut load = LoadAvg::new();
+            load.init(&mut st, Some(String::from(arg)));
+            let info = load.parse_load_avg(&String::from(LOADAVG)).unwrap();
+            load.render_info(&info).get()
+        };
+
+        assert_eq!(render("").0, " 3.20  1.60  0.80");
+        assert_eq!(render("15,1").0, " 0.80  3.20");
+        assert_eq!(render("5 tasks pid").0, " 1.60 5/812 31337");
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut load = LoadAvg::new();
+        load.init(&mut MetricState::new(), Some(String::from("1 norm")));
+        load.ncpu = 4;
+        let info = load.parse_load_avg(&String::from(LOADAVG)).unwrap();
+        let (text, color) = load.render_info(&info).get();
+        assert_eq!(text, " 0.80");
+        // Normalized thresholds: 0.8 per core is above 0.7.
+        assert!(match color {
+            Color::Orange => true,
+            _ => false,
+        });
+        assert!(load.parse_load_avg(&String::new()).is_none());
+    }
+
+    #[test]
+    fn test_configure() {
+        let configure = |arg: &str| LoadAvg::new().configure(arg);
+        assert_eq!(configure("15,5 norm pid"), Ok(()));
+        assert_eq!(configure("nrom"), Err(String::from("unknown option 'nrom'")));
+        assert_eq!(configure("tasks,pid"), Err(String::from("unknown option 'tasks,pid'")));
+        assert_eq!(configure("1,15 5"), Err(String::from("averages given twice: '5'")));
+
+        let mut load = LoadAvg::new();
+        load.init(&mut MetricState::new(), Some(String::from("1,7")));
+        assert_eq!(load.render(&mut MetricState::new()).get().0,
+                   "load: unknown option '1,7'");
+    }
 }
@@ -0,0 +1,354 @@
+//! Counts unread mail in Maildir folders and mbox files.
+
+use framework::*;
+use helper::{commaseparated_to_vec, expand_home, format_template, read_file};
+use inotify;
+use inotify::Inotify;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_FMT: &'static str = "%l:%n";
+
+/// Changes to a Maildir's new/ and cur/ directories; flag changes are renames within cur/.
+const MAILDIR_MASK: u32 = inotify::IN_CREATE | inotify::IN_DELETE | inotify::IN_MOVED_FROM |
+                          inotify::IN_MOVED_TO;
+/// Changes to an mbox file, watched through its directory as it may be replaced.
+const MBOX_MASK: u32 = inotify::IN_MODIFY | inotify::IN_CLOSE_WRITE | inotify::IN_CREATE |
+                       inotify::IN_DELETE | inotify::IN_MOVED_TO;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Counts {
+    /// Messages not yet seen by any mail client.
+    new: usize,
+    /// Messages not marked as read (including new ones).
+    unread: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Maildir,
+    Mbox,
+}
+
+/// Tells a Maildir from an mbox file; None while the path doesn't exist (yet), e.g. because the
+/// file system isn't mounted.
+fn folder_kind(path: &str) -> Option<Kind> {
+    let is_dir = |p: &str| fs::metadata(p).map(|m| m.is_dir()).unwrap_or(false);
+    match fs::metadata(path) {
+        Ok(ref m) if m.is_file() => Some(Kind::Mbox),
+        Ok(_) if is_dir(&format!("{}/cur", path)) => Some(Kind::Maildir),
+        _ => None,
+    }
+}
+
+struct Folder {
+    label: String,
+    path: String,
+    /// Determined once the path exists.
+    kind: Option<Kind>,
+    /// Watch descriptors; empty if the folder couldn't be watched and is rescanned every time.
+    watches: Vec<i32>,
+    counts: Option<Counts>,
+    dirty: bool,
+}
+
+/// Returns whether a message file in cur/ is unread: it has no "S" (seen) flag in its info
+/// part ("<unique>:2,<flags>"). Messages flagged as trashed ("T") don't count.
+fn maildir_unseen(name: &str) -> bool {
+    match name.rfind(":2,") {
+        None => true,
+        Some(i) => {
+            let flags = &name[i + 3..];
+            !flags.contains('S') && !flags.contains('T')
+        }
+    }
+}
+
+fn list_dir(path: &str) -> Option<Vec<String>> {
+    fs::read_dir(path).ok().map(|entries| {
+        entries.filter_map(|e| e.ok())
+               .filter_map(|e| e.file_name().into_string().ok())
+               .filter(|n| !n.starts_with('.'))
+               .collect()
+    })
+}
+
+fn count_maildir(path: &str) -> Option<Counts> {
+    let new = list_dir(&format!("{}/new", path))?.len();
+    let cur = list_dir(&format!("{}/cur", path))?;
+    let unseen = cur.iter().filter(|n| maildir_unseen(n)).count();
+
+    Some(Counts {
+        new: new,
+        unread: new + unseen,
+    })
+}
+
+fn count_message(status: &str, counts: &mut Counts) {
+    if !status.contains('R') {
+        counts.unread += 1;
+        if !status.contains('O') {
+            counts.new += 1;
+        }
+    }
+}
+
+/// Counts the messages of an mbox by their Status header: "R" marks read messages, "O" old
+/// ones, i.e. seen but not read.
+fn parse_mbox(content: &str) -> Counts {
+    let mut counts = Counts { new: 0, unread: 0 };
+    let mut in_headers = false;
+    let mut prev_empty = true;
+    let mut status = String::new();
+
+    for line in content.lines() {
+        if prev_empty && line.starts_with("From ") {
+            if in_headers {
+                count_message(&status, &mut counts);
+            }
+            in_headers = true;
+            status.clear();
+        } else if in_headers && line.is_empty() {
+            count_message(&status, &mut counts);
+            in_headers = false;
+        } else if in_headers && line.to_lowercase().starts_with("status:") {
+            status = String::from(&line[7..]);
+        }
+        prev_empty = line.is_empty();
+    }
+    if in_headers {
+        count_message(&status, &mut counts);
+    }
+    counts
+}
+
+impl Folder {
+    /// Parses "[LABEL=]PATH"; the label defaults to the last path component.
+    fn new(spec: &str) -> Folder {
+        let (label, path) = match spec.find('=') {
+            Some(i) => (String::from(&spec[..i]), expand_home(&spec[i + 1..])),
+            None => {
+                let path = expand_home(spec);
+                let label = Path::new(&path)
+                                .file_name()
+                                .map(|n| n.to_string_lossy().into_owned())
+                                .unwrap_or(path.clone());
+                (label, path)
+            }
+        };
+        Folder {
+            label: label,
+            path: path,
+            kind: None,
+            watches: Vec::new(),
+            counts: None,
+            dirty: true,
+        }
+    }
+
+    fn watch(&mut self, inotify: &mut Inotify) {
+        if self.kind == Some(Kind::Maildir) {
+            for sub in ["new", "cur"].iter() {
+                let dir = format!("{}/{}", self.path, sub);
+                if let Some(wd) = inotify.add_watch(&dir, MAILDIR_MASK) {
+                    self.watches.push(wd);
+                }
+            }
+        } else {
+            let dir = match Path::new(&self.path).parent() {
+                Some(d) if !d.as_os_str().is_empty() => d.to_string_lossy().into_owned(),
+                _ => String::from("."),
+            };
+            if let Some(wd) = inotify.add_watch(&dir, MBOX_MASK) {
+                self.watches.push(wd);
+            }
+        }
+    }
+
+    /// Marks the folder for recounting if the event concerns it.
+    fn handle_event(&mut self, ev: &inotify::Event) {
+        if !self.watches.contains(&ev.wd) {
+            return;
+        }
+        if ev.mask & inotify::IN_IGNORED != 0 {
+            self.watches.retain(|&wd| wd != ev.wd);
+        }
+        let file_name = Path::new(&self.path).file_name().map(|n| n.to_string_lossy());
+        if self.kind == Some(Kind::Maildir) || file_name.map_or(true, |n| n == ev.name) {
+            self.dirty = true;
+        }
+    }
+
+    /// Recounts the messages if the folder has changed or isn't watched. Watches are added
+    /// once the folder exists, and again after they were removed, e.g. by unmounting.
+    fn update(&mut self, inotify: Option<&mut Inotify>) {
+        if self.kind.is_none() {
+            self.kind = folder_kind(&self.path);
+        }
+        if let Some(inotify) = inotify {
+            if self.kind.is_some() && self.watches.is_empty() {
+                self.watch(inotify);
+                self.dirty = true;
+            }
+        }
+        if !self.dirty && !self.watches.is_empty() {
+            return;
+        }
+        self.counts = match self.kind {
+            Some(Kind::Maildir) => count_maildir(&self.path),
+            Some(Kind::Mbox) => read_file(&self.path).map(|c| parse_mbox(&c)),
+            None => None,
+        };
+        self.dirty = false;
+    }
+}
+
+struct MailMetric {
+    folders: Vec<Folder>,
+    fmt: String,
+    hide_zero: bool,
+    inotify: Option<Inotify>,
+}
+
+impl Metric for MailMetric {
+    // arg is a comma-separated list of Maildir folders or mbox files, each optionally prefixed by
+    // a label, and the option "hidezero" to hide folders without unread mail. After ';' follows
+    // the format used for every folder: %l label, %n unread, %N new messages.
+    // "inbox=~/Mail/INBOX,lists=~/Mail/lists hidezero;%l %n"
+    fn init(&mut self, st: &mut MetricState, arg: Option<String>) {
+        let arg = arg.unwrap_or(String::new());
+        let mut parts = arg.splitn(2, ';');
+        let mut words = parts.next().unwrap_or("").split_whitespace();
+
+        self.folders = commaseparated_to_vec(String::from(words.next().unwrap_or("")))
+                           .iter()
+                           .map(|s| s.trim())
+                           .filter(|s| !s.is_empty())
+                           .map(Folder::new)
+                           .collect();
+        self.hide_zero = words.any(|w| w == "hidezero");
+        if let Some(fmt) = parts.next() {
+            self.fmt = String::from(fmt);
+        }
+
+        // The folders are watched on their first update. Render as soon as mail arrives.
+        self.inotify = Inotify::new();
+        if let Some(ref inotify) = self.inotify {
+            st.watch_fd(inotify.fd());
+        }
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        if self.folders.is_empty() {
+            return RenderResult::new(String::from("mail: no folders given"), Color::Red);
+        }
+
+        if let Some(ref mut inotify) = self.inotify {
+            for ev in inotify.read_events() {
+                for folder in self.folders.iter_mut() {
+                    if ev.mask & inotify::IN_Q_OVERFLOW != 0 {
+                        folder.dirty = true;
+                    } else {
+                        folder.handle_event(&ev);
+                    }
+                }
+            }
+        }
+
+        let mut parts = Vec::new();
+        let (mut failed, mut unread) = (false, false);
+        for folder in self.folders.iter_mut() {
+            folder.update(self.inotify.as_mut());
+
+            let mut values = BTreeMap::new();
+            values.insert('l', folder.label.clone());
+            match folder.counts {
+                None => {
+                    values.insert('n', String::from("?"));
+                    values.insert('N', String::from("?"));
+                    failed = true;
+                }
+                Some(counts) => {
+                    if counts.unread == 0 && self.hide_zero {
+                        continue;
+                    }
+                    unread |= counts.unread > 0;
+                    values.insert('n', format!("{}", counts.unread));
+                    values.insert('N', format!("{}", counts.new));
+                }
+            }
+            parts.push(format_template(&self.fmt, &values));
+        }
+
+        if parts.is_empty() {
+            return RenderResult::new(String::from("no mail"), Color::Default);
+        }
+        let color = match (failed, unread) {
+            (true, _) => Color::Red,
+            (false, true) => Color::Green,
+            (false, false) => Color::Default,
+        };
+        RenderResult::new(parts.join(" "), color)
+    }
+}
+
+pub fn make_mail_metric() -> Box<Metric> {
+    Box::new(MailMetric {
+        folders: Vec::new(),
+        fmt: String::from(DEFAULT_FMT),
+        hide_zero: false,
+        inotify: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{maildir_unseen, parse_mbox, Counts, Folder, Kind};
+
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_maildir_unseen() {
+        assert!(maildir_unseen("1490000000.M1P2.host:2,"));
+        assert!(maildir_unseen("1490000000.M1P2.host:2,F"));
+        assert!(!maildir_unseen("1490000000.M1P2.host:2,FS"));
+        assert!(!maildir_unseen("1490000000.M1P2.host:2,T"));
+        assert!(maildir_unseen("1490000000.M1P2.host"));
+    }
+
+    #[test]
+    fn test_parse_mbox() {
+        let mbox = "From alice@example.com Mon Jan  2 10:00:00 2017\nSubject: read\nStatus: \
+                    RO\n\n>From the body, not a new message\n\nFrom bob@example.com Mon Jan  2 \
+                    11:00:00 2017\nSubject: old\nStatus: O\n\nhi\n\nFrom carol@example.com Mon \
+                    Jan  2 12:00:00 2017\nSubject: new\n\n>From quoted\n";
+
+        assert_eq!(parse_mbox(mbox), Counts { new: 1, unread: 2 });
+        assert_eq!(parse_mbox(""), Counts { new: 0, unread: 0 });
+    }
+
+    #[test]
+    fn test_missing_maildir() {
+        let path = format!("{}/myi3stat-test-maildir-{}",
+                           env::temp_dir().display(),
+                           ::std::process::id());
+        let _ = fs::remove_dir_all(&path);
+        let mut folder = Folder::new(&path);
+        folder.update(None);
+        assert_eq!(folder.kind, None);
+        assert_eq!(folder.counts, None);
+
+        for sub in ["new", "cur", "tmp"].iter() {
+            fs::create_dir_all(format!("{}/{}", path, sub)).unwrap();
+        }
+        fs::write(format!("{}/new/1490000000.M1P2.host", path), "").unwrap();
+        folder.update(None);
+        assert_eq!(folder.kind, Some(Kind::Maildir));
+        assert_eq!(folder.counts, Some(Counts { new: 1, unread: 1 }));
+
+        let _ = fs::remove_dir_all(&path);
+    }
+}
@@ -1,4 +1,21 @@
+pub mod agenda;
+pub mod backlight;
+pub mod containers;
 pub mod cpu_load;
+pub mod cpufreq;
+pub mod i3;
+pub mod ipaddr;
+pub mod kbd;
 pub mod load;
+pub mod mail;
+pub mod mpd;
 pub mod net;
+pub mod proc_watch;
+pub mod psi;
+pub mod systemd;
 pub mod time;
+pub mod timer;
+pub mod top;
+pub mod uptime;
+pub mod volume;
+pub mod wifi;
@@ -0,0 +1,451 @@
+//! Shows what MPD is playing. A thread waits for changes with "idle player mixer" and triggers a
+//! render when something happened; clicks control playback.
+
+use framework::*;
+use helper::{expand_home, format_template};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_FMT: &'static str = "%s %a - %t [%e/%d]";
+const DEFAULT_PORT: u16 = 6600;
+/// Time to wait before reconnecting after the connection to MPD was lost, in milliseconds.
+const RECONNECT_DELAY: u64 = 2000;
+/// Timeout for commands sent after clicks, so that the bar doesn't hang on a stuck MPD.
+const COMMAND_TIMEOUT: u64 = 1000;
+/// Timeout for establishing a TCP connection when no other timeout is given.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Address {
+    Tcp(String, u16),
+    Unix(String),
+}
+
+/// Determines where MPD listens and the password, if any. arg is "[PASSWORD@]HOST[:PORT]" or a
+/// socket path; without it, $MPD_HOST and $MPD_PORT are used like mpc(1) does.
+fn parse_address(arg: Option<&str>,
+                 env_host: Option<String>,
+                 env_port: Option<String>)
+                 -> (Address, Option<String>) {
+    let (spec, mut port) = match arg {
+        Some(a) if !a.is_empty() => (String::from(a), None),
+        _ => {
+            (env_host.unwrap_or(String::from("localhost")),
+             env_port.and_then(|p| u16::from_str(&p).ok()))
+        }
+    };
+    let (password, host) = match spec.rfind('@') {
+        Some(i) if i > 0 => (Some(String::from(&spec[..i])), String::from(&spec[i + 1..])),
+        _ => (None, spec),
+    };
+
+    if host.starts_with('/') || host.starts_with('~') {
+        return (Address::Unix(expand_home(&host)), password);
+    }
+    // IPv6 addresses are enclosed in brackets when followed by a port: "[::1]:6600".
+    let (host, port_str) = match (host.starts_with('['), host.find(']')) {
+        (true, Some(i)) => {
+            let rest = &host[i + 1..];
+            (String::from(&host[1..i]), if rest.starts_with(':') { Some(&rest[1..]) } else { None })
+        }
+        _ if host.matches(':').count() == 1 => {
+            let i = host.find(':').unwrap();
+            (String::from(&host[..i]), Some(&host[i + 1..]))
+        }
+        _ => (host.clone(), None),
+    };
+    if let Some(p) = port_str {
+        port = u16::from_str(p).ok().or(port);
+    }
+    (Address::Tcp(host, port.unwrap_or(DEFAULT_PORT)), password)
+}
+
+/// Connects to the first address of host that accepts within timeout; TcpStream::connect() could
+/// block for minutes on an unreachable host.
+fn connect_tcp(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, String> {
+    let mut error = format!("{}: no address found", host);
+    for addr in (host, port).to_socket_addrs().map_err(|e| format!("{}: {}", host, e))? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(s) => return Ok(s),
+            Err(e) => error = format!("{}: {}", addr, e),
+        }
+    }
+    Err(error)
+}
+
+/// A connection speaking MPD's line protocol.
+struct Connection {
+    reader: BufReader<Box<Read + Send>>,
+    writer: Box<Write + Send>,
+}
+
+impl Connection {
+    fn connect(addr: &Address,
+               password: &Option<String>,
+               timeout: Option<Duration>)
+               -> Result<Connection, String> {
+        let (reader, writer): (Box<Read + Send>, Box<Write + Send>) = match *addr {
+            Address::Tcp(ref host, port) => {
+                let s = connect_tcp(host, port, timeout.unwrap_or(CONNECT_TIMEOUT))?;
+                s.set_read_timeout(timeout).map_err(|e| e.to_string())?;
+                (Box::new(s.try_clone().map_err(|e| e.to_string())?), Box::new(s))
+            }
+            Address::Unix(ref path) => {
+                let s = UnixStream::connect(path).map_err(|e| e.to_string())?;
+                s.set_read_timeout(timeout).map_err(|e| e.to_string())?;
+                (Box::new(s.try_clone().map_err(|e| e.to_string())?), Box::new(s))
+            }
+        };
+        let mut conn = Connection {
+            reader: BufReader::new(reader),
+            writer: writer,
+        };
+
+        if !conn.read_line()?.starts_with("OK MPD") {
+            return Err(String::from("not an MPD server"));
+        }
+        if let Some(ref pw) = *password {
+            conn.command(&format!("password {}", quote(pw)))?;
+        }
+        Ok(conn)
+    }
+
+    fn read_line(&mut self) -> Result<String, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Err(e) => Err(e.to_string()),
+            Ok(0) => Err(String::from("connection closed")),
+            Ok(_) => Ok(String::from(line.trim_end_matches('\n'))),
+        }
+    }
+
+    /// Sends a command and returns the "key: value" pairs of the response.
+    fn command(&mut self, cmd: &str) -> Result<Vec<(String, String)>, String> {
+        self.writer.write_all(format!("{}\n", cmd).as_bytes()).map_err(|e| e.to_string())?;
+
+        let mut pairs = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line == "OK" {
+                return Ok(pairs);
+            }
+            if line.starts_with("ACK") {
+                return Err(line);
+            }
+            if let Some(i) = line.find(": ") {
+                pairs.push((String::from(&line[..i]), String::from(&line[i + 2..])));
+            }
+        }
+    }
+}
+
+/// Quotes a command argument.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[derive(Default)]
+struct MpdState {
+    /// None while the first connection attempt is in progress.
+    connected: Option<bool>,
+    /// "play", "pause" or "stop".
+    state: String,
+    /// Fields of the current song (Artist, Title, Album, file, ...).
+    song: BTreeMap<String, String>,
+    /// Seconds; elapsed as of updated_at.
+    elapsed: f64,
+    duration: f64,
+    volume: Option<i32>,
+    /// When status was last read, in epoch milliseconds.
+    updated_at: i64,
+}
+
+impl MpdState {
+    fn update(&mut self, status: Vec<(String, String)>, song: Vec<(String, String)>) {
+        self.song = song.into_iter().collect();
+        self.elapsed = 0.;
+        self.duration = self.song.get("Time").and_then(|t| f64::from_str(t).ok()).unwrap_or(0.);
+        self.volume = None;
+
+        for (key, value) in status {
+            match key.as_str() {
+                "state" => self.state = value,
+                "elapsed" => self.elapsed = f64::from_str(&value).unwrap_or(0.),
+                "duration" => self.duration = f64::from_str(&value).unwrap_or(self.duration),
+                "volume" => self.volume = i32::from_str(&value).ok().filter(|&v| v >= 0),
+                _ => (),
+            }
+        }
+        self.updated_at = MetricState::now();
+        self.connected = Some(true);
+    }
+
+    /// Elapsed seconds at now, extrapolated while playing.
+    fn elapsed_at(&self, now: i64) -> f64 {
+        if self.state != "play" {
+            return self.elapsed;
+        }
+        let elapsed = self.elapsed + (now - self.updated_at) as f64 / 1000.;
+        if self.duration > 0. { elapsed.min(self.duration) } else { elapsed }
+    }
+}
+
+fn format_time(secs: f64) -> String {
+    let secs = secs as i64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Reads status and current song, then waits for changes, until the connection fails or the
+/// metric has been dropped.
+fn session(addr: &Address,
+           password: &Option<String>,
+           state: &Arc<Mutex<MpdState>>,
+           notifier: &Option<Notifier>)
+           -> Result<(), String> {
+    let mut conn = Connection::connect(addr, password, None)?;
+
+    while Arc::strong_count(state) > 1 {
+        let status = conn.command("status")?;
+        let song = conn.command("currentsong")?;
+        state.lock().unwrap().update(status, song);
+        if let Some(ref n) = *notifier {
+            n.notify();
+        }
+        conn.command("idle player mixer")?;
+    }
+    Ok(())
+}
+
+fn watch(addr: Address,
+         password: Option<String>,
+         state: Arc<Mutex<MpdState>>,
+         notifier: Option<Notifier>) {
+    while Arc::strong_count(&state) > 1 {
+        if session(&addr, &password, &state, &notifier).is_err() {
+            state.lock().unwrap().connected = Some(false);
+            if let Some(ref n) = notifier {
+                n.notify();
+            }
+        }
+        thread::sleep(Duration::from_millis(RECONNECT_DELAY));
+    }
+}
+
+struct MpdMetric {
+    addr: Address,
+    password: Option<String>,
+    fmt: String,
+    state: Arc<Mutex<MpdState>>,
+}
+
+impl MpdMetric {
+    fn new() -> MpdMetric {
+        MpdMetric {
+            addr: Address::Tcp(String::from("localhost"), DEFAULT_PORT),
+            password: None,
+            fmt: String::from(DEFAULT_FMT),
+            state: Arc::new(Mutex::new(MpdState::default())),
+        }
+    }
+
+    fn start(&self, notifier: Option<Notifier>) {
+        let (addr, password) = (self.addr.clone(), self.password.clone());
+        let state = self.state.clone();
+        thread::spawn(move || watch(addr, password, state, notifier));
+    }
+
+    /// Sends a single command on a new connection; the idle connection notices the effect.
+    fn send(&self, cmd: &str) -> Result<(), String> {
+        let timeout = Some(Duration::from_millis(COMMAND_TIMEOUT));
+        Connection::connect(&self.addr, &self.password, timeout)?
+            .command(cmd)
+            .map(|_| ())
+    }
+}
+
+impl Metric for MpdMetric {
+    // arg is the address ("[PASSWORD@]HOST[:PORT]" or a socket path, defaulting to $MPD_HOST and
+    // $MPD_PORT), optionally followed by ';' and a format: %a artist, %t title, %A album, %e
+    // elapsed, %d duration, %s state symbol, %v volume. "localhost:6600;%a - %t"
+    fn init(&mut self, st: &mut MetricState, arg: Option<String>) {
+        let arg = arg.unwrap_or(String::new());
+        let mut parts = arg.splitn(2, ';');
+
+        let (addr, password) = parse_address(parts.next().map(str::trim),
+                                             env::var("MPD_HOST").ok(),
+                                             env::var("MPD_PORT").ok());
+        self.addr = addr;
+        self.password = password;
+        if let Some(fmt) = parts.next() {
+            self.fmt = String::from(fmt);
+        }
+        self.start(st.notifier());
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        let st = self.state.lock().unwrap();
+
+        match (st.connected, st.state.as_str()) {
+            (None, _) => return RenderResult::new(String::new(), Color::Default),
+            (Some(false), _) => return RenderResult::new(String::from("mpd n/a"), Color::Red),
+            (_, "stop") => return RenderResult::new(String::from("■"), Color::Default),
+            _ => (),
+        }
+
+        let field = |key: &str| st.song.get(key).cloned().unwrap_or(String::new());
+        let title = match st.song.get("Title").or(st.song.get("Name")) {
+            Some(t) => t.clone(),
+            // Fall back to the file name.
+            None => field("file").rsplit('/').next().unwrap_or("").to_string(),
+        };
+
+        let mut values = BTreeMap::new();
+        values.insert('a', field("Artist"));
+        values.insert('t', title);
+        values.insert('A', field("Album"));
+        values.insert('e', format_time(st.elapsed_at(MetricState::now())));
+        values.insert('d', format_time(st.duration));
+        values.insert('s', String::from(if st.state == "play" { "▶" } else { "⏸" }));
+        values.insert('v', st.volume.map(|v| format!("{}%", v)).unwrap_or(String::new()));
+
+        let color = if st.state == "play" { Color::Default } else { Color::Orange };
+        RenderResult::new(format_template(&self.fmt, &values), color)
+    }
+
+    // Button 1 toggles play/pause, button 2 skips to the previous and button 3 to the next song.
+    fn click(&mut self, _: &mut MetricState, ev: &ClickEvent) {
+        let playing = self.state.lock().unwrap().state == "play";
+        let cmd = match ev.button {
+            1 if playing => "pause 1",
+            1 => "play",
+            2 => "previous",
+            3 => "next",
+            _ => return,
+        };
+        if let Err(e) = self.send(cmd) {
+            eprintln!("myi3stat: mpd: {}: {}", cmd, e);
+        }
+    }
+}
+
+pub fn make_mpd_metric() -> Box<Metric> {
+    Box::new(MpdMetric::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address(None, None, None),
+                   (Address::Tcp(String::from("localhost"), 6600), None));
+        let (host, port) = (Some(String::from("secret@music")), Some(String::from("7700")));
+        assert_eq!(parse_address(None, host, port),
+                   (Address::Tcp(String::from("music"), 7700), Some(String::from("secret"))));
+        assert_eq!(parse_address(Some("music:7000"), None, Some(String::from("7700"))),
+                   (Address::Tcp(String::from("music"), 7000), None));
+        assert_eq!(parse_address(Some("/run/mpd/socket"), None, None),
+                   (Address::Unix(String::from("/run/mpd/socket")), None));
+        assert_eq!(parse_address(Some("pw@[::1]:7000"), None, None),
+                   (Address::Tcp(String::from("::1"), 7000), Some(String::from("pw"))));
+        assert_eq!(parse_address(None, Some(String::from("[fe80::1]")), None),
+                   (Address::Tcp(String::from("fe80::1"), 6600), None));
+        assert_eq!(parse_address(Some("::1"), None, None),
+                   (Address::Tcp(String::from("::1"), 6600), None));
+    }
+
+    #[test]
+    fn test_elapsed() {
+        let mut st = MpdState::default();
+        st.state = String::from("play");
+        st.elapsed = 10.;
+        st.duration = 12.;
+        st.updated_at = 1000;
+        assert_eq!(st.elapsed_at(2500), 11.5);
+        assert_eq!(st.elapsed_at(9000), 12.);
+        assert_eq!(format_time(125.7), "2:05");
+        assert_eq!(format_time(3725.), "1:02:05");
+    }
+
+    /// Answers like MPD: first stopped, then playing after an idle; records the commands sent on
+    /// further connections.
+    fn stand_in(listener: TcpListener, commands: Arc<Mutex<Vec<String>>>) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        writer.write_all(b"OK MPD 0.23.5\n").unwrap();
+
+        let mut playing = false;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 {
+            let reply = match line.trim() {
+                "status" if playing => "state: play\nelapsed: 61.500\nduration: 200.0\nOK\n",
+                "status" => "volume: 50\nstate: stop\nOK\n",
+                "currentsong" if playing => "file: a/b.ogg\nArtist: Some \"Band\"\nOK\n",
+                "currentsong" => "OK\n",
+                "idle player mixer" if !playing => {
+                    playing = true;
+                    // A click from the bar arrives on a second connection meanwhile.
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut click = BufReader::new(stream.try_clone().unwrap());
+                    let mut out = stream;
+                    out.write_all(b"OK MPD 0.23.5\n").unwrap();
+                    let mut cmd = String::new();
+                    click.read_line(&mut cmd).unwrap();
+                    commands.lock().unwrap().push(String::from(cmd.trim()));
+                    out.write_all(b"OK\n").unwrap();
+                    "changed: player\nOK\n"
+                }
+                // Block until the client hangs up.
+                _ => "",
+            };
+            writer.write_all(reply.as_bytes()).unwrap();
+            line.clear();
+        }
+    }
+
+    #[test]
+    fn test_session_and_click() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let cmds = commands.clone();
+        thread::spawn(move || stand_in(listener, cmds));
+
+        let (tx, rx) = channel();
+        let mut st = MetricState::with_wakeups(tx);
+        let mut metric = MpdMetric::new();
+        metric.addr = Address::Tcp(String::from("127.0.0.1"), port);
+        metric.start(st.notifier());
+
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(metric.render(&mut st).get().0, "■");
+
+        metric.click(&mut st,
+                     &ClickEvent {
+                         name: String::from("mpd"),
+                         instance: None,
+                         button: 1,
+                     });
+        assert_eq!(*commands.lock().unwrap(), vec![String::from("play")]);
+
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(metric.render(&mut st).get().0, "▶ Some \"Band\" - b.ogg [1:01/3:20]");
+    }
+}
@@ -1,142 +1,418 @@
 use framework::*;
-use helper::{commaseparated_to_vec, get_procfs_file_lines, extract_from_str};
+use helper::{commaseparated_to_vec, read_file, read_procfs_file, read_sysfs_file,
+             scale_to_unit, state_file_path, write_file};
 
+extern crate chrono;
 extern crate regex;
 use self::regex::Regex;
 
+use std::cmp::max;
 use std::collections::BTreeMap;
 
+/// File in the state directory keeping the daily traffic per interface.
+const DAILY_STATE_FILE: &'static str = "netif-daily";
+/// Minimum time between writes of the daily traffic file, in milliseconds.
+const DAILY_SAVE_INTERVAL: i64 = 60000;
+
 // Interface name, transmitted bytes, received bytes. Used for both rates and counters!
 type IFStat = (String, u64, u64);
 
+/// Determines which interfaces are shown.
+enum Selection {
+    /// All interfaces that are up, except for loopback interfaces.
+    Auto,
+    /// All interfaces matching one of the patterns.
+    Patterns(Vec<Regex>),
+}
+
 struct NetInterfaceMetric {
+    selection: Selection,
+    /// Show the sum over all selected interfaces instead of individual rates.
+    aggregate: bool,
+    show_session: bool,
+    show_daily: bool,
     oldstat: BTreeMap<String, IFStat>,
-    whole_line_re: Regex,
-    interface_re: Regex,
+
+    /// Bytes transferred per interface since myi3stat was started.
+    session: BTreeMap<String, IFStat>,
+    /// Bytes transferred per interface on the day daily_date; persisted in DAILY_STATE_FILE.
+    daily: BTreeMap<String, IFStat>,
+    daily_date: String,
+    daily_saved: i64,
+    error: Option<String>,
 }
 
-impl NetInterfaceMetric {
-    fn new() -> NetInterfaceMetric {
-        NetInterfaceMetric { oldstat: BTreeMap::new(),
-        whole_line_re: Regex::new(r"^\s*[a-z0-9]+:\s+(\d+)\s+(\d+)\s+\d+\s+\d+\s+\d+\s+\d+\s+\d+\s+\d+\s+(\d+)\s+(\d+)\s+\d+\s+\d+\s+\d+\s+\d+\s+\d+\s+\d+$").unwrap(),
-        interface_re: Regex::new(r"^\s*([a-z0-9]+):.+").unwrap()}
+/// Returns by how much a counter has increased. A counter that decreased has either wrapped
+/// around (32 bit counters on some drivers) or was reset because the interface was re-created;
+/// whichever explanation results in the smaller increase is assumed.
+fn counter_delta(old: u64, new: u64) -> u64 {
+    const WRAP: u64 = 1 << 32;
+
+    if new >= old {
+        new - old
+    } else if old < WRAP && WRAP - old + new < new.saturating_add(WRAP / 2) {
+        WRAP - old + new
+    } else {
+        new
     }
-    /// Obtain current counters from /proc/net/dev
-    fn get_stats(&self, ifs: &BTreeMap<String, IFStat>) -> Vec<IFStat> {
-        let ifstats;
-        let mut processed_stats = Vec::with_capacity(ifs.len());
+}
 
-        match get_procfs_file_lines(String::from("/net/dev")) {
-            None => ifstats = Vec::new(),
-            Some(st) => ifstats = st,
-        }
+fn today() -> String {
+    format!("{}", chrono::Local::now().format("%Y-%m-%d"))
+}
 
-        //               RX                                                             TX
-        //           *           *                                                    *         *
-        //  iface |bytes       packets  errs drop fifo frame   compressed multicast|bytes     packets errs drop fifo colls   carrier compressed
-        //  eth0:  1037503524  872642    0    0    0     0          0     10482     40971427  300143    0    1    0     0       0          0
+/// Parses the contents of /proc/net/dev into (interface, rx bytes, tx bytes) tuples.
+///
+///               RX                                                             TX
+///           *           *                                                    *         *
+///  iface |bytes       packets  errs drop fifo frame   compressed multicast|bytes     packets errs drop fifo colls   carrier compressed
+///  eth0:  1037503524  872642    0    0    0     0          0     10482     40971427  300143    0    1    0     0       0          0
+fn parse_net_dev(content: &str) -> Vec<IFStat> {
+    let mut stats = Vec::new();
 
-        for line in ifstats {
-            let results: Vec<String> = extract_from_str(&line,
-                                                        &self.interface_re,
-                                                        String::from(""));
+    for line in content.lines() {
+        // The counters never contain a colon, but interface names might.
+        let colon = match line.rfind(':') {
+            None => continue,
+            Some(i) => i,
+        };
+        let interface = line[..colon].trim();
+        let counters: Vec<u64> = line[colon + 1..]
+                                     .split_whitespace()
+                                     .map(|c| c.parse().unwrap_or(0))
+                                     .collect();
 
-            if results.len() < 1 {
+        if interface.is_empty() || counters.len() < 9 {
+            continue;
+        }
+        stats.push((String::from(interface), counters[0], counters[8]));
+    }
+    stats
+}
+
+/// Converts an interface pattern to an anchored regular expression. Patterns enclosed in slashes
+/// are regular expressions ("/^en.*[0-9]$/"); all others are globs supporting * and ?.
+fn pattern_to_regex(pattern: &str) -> Result<Regex, String> {
+    let re = if pattern.len() >= 2 && pattern.starts_with("/") && pattern.ends_with("/") {
+        format!("^(?:{})$", &pattern[1..pattern.len() - 1])
+    } else {
+        let mut re = String::from("^");
+        let mut literal = String::new();
+        for c in pattern.chars() {
+            if c != '*' && c != '?' {
+                literal.push(c);
                 continue;
             }
+            re.push_str(&regex::quote(&literal));
+            literal.clear();
+            re.push_str(if c == '*' { ".*" } else { "." });
+        }
+        re.push_str(&regex::quote(&literal));
+        re.push('$');
+        re
+    };
+    Regex::new(&re).map_err(|_| format!("invalid interface pattern '{}'", pattern))
+}
 
-            let interface = &results[0];
+impl NetInterfaceMetric {
+    fn new() -> NetInterfaceMetric {
+        NetInterfaceMetric {
+            selection: Selection::Auto,
+            aggregate: false,
+            show_session: false,
+            show_daily: false,
+            oldstat: BTreeMap::new(),
+            session: BTreeMap::new(),
+            daily: BTreeMap::new(),
+            daily_date: String::new(),
+            daily_saved: 0,
+            error: None,
+        }
+    }
 
-            if ifs.contains_key(interface) {
-                let stats: Vec<u64> = extract_from_str(&line, &self.whole_line_re, 0);
-                assert!(stats.len() > 3);
-                processed_stats.push((interface.clone(), stats[0], stats[2]));
+    /// Loads the daily traffic counters. The file's first line is the date, followed by lines of
+    /// "<interface> <rx bytes> <tx bytes>".
+    fn load_daily(&mut self) {
+        let content = state_file_path(DAILY_STATE_FILE)
+                          .and_then(|path| read_file(&path))
+                          .unwrap_or(String::new());
+        let mut lines = content.lines();
 
+        self.daily_date = today();
+        if lines.next() != Some(&self.daily_date) {
+            return;
+        }
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() == 3 {
+                let rx = fields[1].parse().unwrap_or(0);
+                let tx = fields[2].parse().unwrap_or(0);
+                self.daily.insert(String::from(fields[0]), (String::from(fields[0]), rx, tx));
             }
         }
+    }
+
+    fn save_daily(&mut self, now: i64) {
+        let mut content = format!("{}\n", self.daily_date);
+        for &(ref i, rx, tx) in self.daily.values() {
+            content.push_str(&format!("{} {} {}\n", i, rx, tx));
+        }
+        if let Some(path) = state_file_path(DAILY_STATE_FILE) {
+            write_file(&path, &content);
+        }
+        self.daily_saved = now;
+    }
+
+    /// Adds transferred bytes to the session and daily counters.
+    fn account(&mut self, ifname: &str, rx: u64, tx: u64) {
+        for totals in vec![&mut self.session, &mut self.daily] {
+            let entry = totals.entry(String::from(ifname))
+                              .or_insert((String::from(ifname), 0, 0));
+            entry.1 += rx;
+            entry.2 += tx;
+        }
+    }
+
+    /// Adds the bytes transferred since the previous counters to the totals and returns them per
+    /// interface; interfaces seen for the first time only provide a baseline.
+    fn update_counters(&mut self, newstats: Vec<IFStat>) -> Vec<IFStat> {
+        if self.show_daily && self.daily_date != today() {
+            self.daily.clear();
+            self.daily_date = today();
+        }
+
+        let mut deltas = Vec::new();
+        let mut counters = BTreeMap::new();
+        for (ifname, rx, tx) in newstats {
+            let (drx, dtx) = match self.oldstat.get(&ifname) {
+                Some(&(_, oldrx, oldtx)) => (counter_delta(oldrx, rx), counter_delta(oldtx, tx)),
+                None => (0, 0),
+            };
+
+            self.account(&ifname, drx, dtx);
+            deltas.push((ifname.clone(), drx, dtx));
+            // Interfaces that disappeared are forgotten.
+            counters.insert(ifname.clone(), (ifname, rx, tx));
+        }
+        self.oldstat = counters;
+        deltas
+    }
+
+    /// Returns the totals for an interface, or the sum over all interfaces in aggregate mode.
+    fn totals_for(&self, totals: &BTreeMap<String, IFStat>, ifname: &str) -> (u64, u64) {
+        if self.aggregate {
+            totals.values().fold((0, 0), |(rx, tx), &(_, r, t)| (rx + r, tx + t))
+        } else {
+            totals.get(ifname).map_or((0, 0), |&(_, rx, tx)| (rx, tx))
+        }
+    }
+
+    /// Returns true if the interface is up and not a loopback interface.
+    fn is_up_non_loopback(interface: &str) -> bool {
+        const IFF_LOOPBACK: u32 = 0x8;
+
+        let flags = read_sysfs_file(format!("/class/net/{}/flags", interface))
+                        .and_then(|f| u32::from_str_radix(f.trim_start_matches("0x"), 16).ok())
+                        .unwrap_or(IFF_LOOPBACK);
+        // Tunnel devices usually report "unknown".
+        let up = match read_sysfs_file(format!("/class/net/{}/operstate", interface)) {
+            Some(ref s) => s == "up" || s == "unknown",
+            None => false,
+        };
+        up && flags & IFF_LOOPBACK == 0
+    }
+
+    fn is_selected(&self, interface: &str) -> bool {
+        match self.selection {
+            Selection::Auto => NetInterfaceMetric::is_up_non_loopback(interface),
+            Selection::Patterns(ref pats) => pats.iter().any(|re| re.is_match(interface)),
+        }
+    }
 
-        processed_stats
+    /// Obtain current counters of all selected interfaces from /proc/net/dev. The selection is
+    /// evaluated every time, so that interfaces appearing later (e.g. VPN tunnels) are picked up.
+    fn get_stats(&self) -> Vec<IFStat> {
+        parse_net_dev(&read_procfs_file(String::from("/net/dev")).unwrap_or(String::new()))
+            .into_iter()
+            .filter(|&(ref interface, _, _)| self.is_selected(interface))
+            .collect()
     }
 
     /// Convert a number into a string with nice unit
     fn make_nice_rate(i: u64) -> String {
-        let units = vec!["", "K", "M", "G"];
-        let mut u = 0;
-        let mut f = i as f64;
-        loop {
-            if f / 1024. > 1. {
-                f = f / 1024.;
-                u += 1;
-            } else {
-                break;
-            }
-        }
-        assert!(u < 4);
-        format!("{:6.1}{:1}", f, units[u])
+        let (f, unit) = scale_to_unit(i as f64, 1024., &["", "K", "M", "G", "T", "P"]);
+        format!("{:6.1}{:1}", f, unit)
     }
 
-    /// Format a series of IFStat tuples
-    fn format_stats(stats: Vec<IFStat>) -> String {
+    /// Format a series of IFStat tuples, appending session and daily totals if requested.
+    fn format_stats(&self, stats: Vec<IFStat>) -> String {
         stats.into_iter()
              .fold(String::new(), |mut acc, (i, rx, tx)| {
                  acc.push_str(&format!("{}: rx:{} tx:{} ",
                                        i,
                                        NetInterfaceMetric::make_nice_rate(rx),
                                        NetInterfaceMetric::make_nice_rate(tx)));
+                 if self.show_session {
+                     let (rx, tx) = self.totals_for(&self.session, &i);
+                     acc.push_str(&format!("session:{}/{} ",
+                                           NetInterfaceMetric::make_nice_rate(rx).trim(),
+                                           NetInterfaceMetric::make_nice_rate(tx).trim()));
+                 }
+                 if self.show_daily {
+                     let (rx, tx) = self.totals_for(&self.daily, &i);
+                     acc.push_str(&format!("today:{}/{} ",
+                                           NetInterfaceMetric::make_nice_rate(rx).trim(),
+                                           NetInterfaceMetric::make_nice_rate(tx).trim()));
+                 }
                  acc
              })
     }
 }
 
 impl Metric for NetInterfaceMetric {
+    // arg is a comma-separated list of interface patterns (see pattern_to_regex()), or "auto" to
+    // show all interfaces that are up. It may be followed by whitespace-separated options:
+    // "total" shows the sum over all selected interfaces, "session" the bytes transferred since
+    // startup, "daily" the bytes transferred today: "eth*,wl* total daily"
     fn init(&mut self, _: &mut MetricState, initarg: Option<String>) {
-        match initarg {
-            None => (),
-            Some(s) => {
-                let mut wanted_ifs = BTreeMap::new();
-                for intf in commaseparated_to_vec(s) {
-                    wanted_ifs.insert(intf.clone(), (intf, 0, 0));
-                }
-                self.oldstat = wanted_ifs;
+        let arg = initarg.unwrap_or(String::from("auto"));
+        let mut parts = arg.split_whitespace();
+        let patterns = parts.next().unwrap_or("auto");
+
+        if patterns != "auto" {
+            let regexes = commaseparated_to_vec(String::from(patterns))
+                              .iter()
+                              .map(|p| pattern_to_regex(p))
+                              .collect();
+            match regexes {
+                Ok(regexes) => self.selection = Selection::Patterns(regexes),
+                Err(e) => self.error = Some(format!("netif: {}", e)),
             }
         }
+        for opt in parts {
+            match opt {
+                "total" => self.aggregate = true,
+                "session" => self.show_session = true,
+                "daily" => self.show_daily = true,
+                _ => (),
+            }
+        }
+        if self.show_daily {
+            self.load_daily();
+        }
     }
 
     fn render(&mut self, st: &mut MetricState) -> RenderResult {
-        let interval = (MetricState::now() - st.last_called) as u64;
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
+        let now = MetricState::now();
+        let interval = max(now - st.last_called, 1) as u64;
 
-        if self.oldstat.is_empty() {
+        // Get current counters
+        let newstats = self.get_stats();
+
+        if newstats.is_empty() {
+            self.oldstat.clear();
             return RenderResult::new(String::from("n/a"), Color::Red);
         }
 
-        // Get current counters
-        let newstats = self.get_stats(&self.oldstat);
-        let mut rates: Vec<IFStat> = Vec::new(); // this is the final output
+        let mut rates: Vec<IFStat> = self.update_counters(newstats)
+                                         .into_iter()
+                                         .map(|(ifname, drx, dtx)| {
+                                             (ifname, 1000 * drx / interval, 1000 * dtx / interval)
+                                         })
+                                         .collect();
 
-        for (ifname, rx, tx) in newstats {
-            // Obtain previous rx/tx counts from state
-            let oldstat;
-            match self.oldstat.get(&ifname) {
-                Some(o) => oldstat = o.clone(),
-                _ => oldstat = (ifname.clone(), rx, tx),
-            }
+        if self.show_daily && now - self.daily_saved >= DAILY_SAVE_INTERVAL {
+            self.save_daily(now);
+        }
 
-            // calculate rate over last interval
-            match oldstat {
-                (ref ifname, oldrx, oldtx) => {
-                    rates.push((ifname.clone(),
-                                1000 * (rx - oldrx) / interval,
-                                1000 * (tx - oldtx) / interval));
-                }
-            }
-            // Store current counters in state
-            self.oldstat.insert(ifname.clone(), (ifname, rx, tx));
+        if self.aggregate {
+            rates = vec![rates.into_iter().fold((String::from("total"), 0, 0),
+                                                |(i, rx, tx), (_, r, t)| (i, rx + r, tx + t))];
         }
 
-        RenderResult::new(NetInterfaceMetric::format_stats(rates), Color::Green)
+        RenderResult::new(self.format_stats(rates), Color::Green)
+    }
+
+    // The traffic while paused still counts towards the totals, but not towards the next rate.
+    fn reset(&mut self, _: &mut MetricState) {
+        let newstats = self.get_stats();
+        self.update_counters(newstats);
     }
 }
 
 pub fn make_net_metric() -> Box<Metric> {
     Box::new(NetInterfaceMetric::new())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{counter_delta, parse_net_dev, pattern_to_regex, NetInterfaceMetric};
+
+    const PROC_NET_DEV: &'static str = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:  123456     100    0    0    0     0          0         0   123456     100    0    0    0     0       0          0
+wlp3s0-1: 1037503524  872642    0    0    0     0          0     10482 40971427  300143    0    1    0     0       0          0
+br-abc:1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16
+enp0s31f6.100: 5 0 0 0 0 0 0 0 6 0 0 0 0 0 0 0
+";
+
+    #[test]
+    fn test_parse_net_dev() {
+        let stats = parse_net_dev(PROC_NET_DEV);
+
+        assert_eq!(stats,
+                   vec![(String::from("lo"), 123456, 123456),
+                        (String::from("wlp3s0-1"), 1037503524, 40971427),
+                        (String::from("br-abc"), 1, 9),
+                        (String::from("enp0s31f6.100"), 5, 6)]);
+    }
+
+    #[test]
+    fn test_pattern_to_regex() {
+        let glob = pattern_to_regex("en*.1?0").unwrap();
+        assert!(glob.is_match("enp0s31f6.100"));
+        assert!(!glob.is_match("enp0s31f6x100"));
+        assert!(!glob.is_match("xenp0s31f6.100"));
+
+        let re = pattern_to_regex("/veth.*|br-[a-f]+/").unwrap();
+        assert!(re.is_match("veth@x"));
+        assert!(re.is_match("br-abc"));
+        assert!(!re.is_match("eth0"));
+
+        // Punctuation that isn't special in regexes is taken literally.
+        let glob = pattern_to_regex("br-*").unwrap();
+        assert!(glob.is_match("br-4f2a91"));
+        assert!(!glob.is_match("bridge0"));
+        assert!(pattern_to_regex("wg_*").unwrap().is_match("wg_home"));
+        assert!(pattern_to_regex("veth@*").unwrap().is_match("veth@if12"));
+        assert!(pattern_to_regex("/br-[/").is_err());
+    }
+
+    #[test]
+    fn test_counter_delta() {
+        assert_eq!(counter_delta(100, 150), 50);
+        // 32 bit wraparound
+        assert_eq!(counter_delta(4294967000, 100), 396);
+        // interface was re-created
+        assert_eq!(counter_delta(1000000, 500), 500);
+        assert_eq!(counter_delta(10000000000, 500), 500);
+    }
+
+    #[test]
+    fn test_reset_keeps_totals() {
+        let stats = |rx, tx| vec![(String::from("wlan0"), rx, tx)];
+        let mut metric = NetInterfaceMetric::new();
+
+        assert_eq!(metric.update_counters(stats(1000, 100)), stats(0, 0));
+        assert_eq!(metric.update_counters(stats(1500, 200)), stats(500, 100));
+        // Paused, then reset (which reads the counters like this): the traffic in between is
+        // added to the totals, but the next rate only covers the time since.
+        metric.update_counters(stats(4500, 1100));
+        assert_eq!(metric.update_counters(stats(5000, 1200)), stats(500, 100));
+        assert_eq!(metric.session.get("wlan0"),
+                   Some(&(String::from("wlan0"), 4000, 1100)));
+    }
+}
@@ -0,0 +1,283 @@
+//! Watches whether given processes are running: by name, by a regex on their command line or by
+//! a PID file. Missing processes turn the block red and urgent.
+
+use framework::*;
+use helper::{expand_home, format_kib, format_template, read_file};
+use procs;
+use procs::{CpuUsage, Process};
+
+extern crate regex;
+use self::regex::Regex;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+const DEFAULT_FMT: &'static str = "%l %n";
+/// The kernel truncates process names (comm) to this length.
+const COMM_LEN: usize = 15;
+
+enum Matcher {
+    /// Process name as shown by ps -e.
+    Name(String),
+    /// Regex on the command line, arguments separated by spaces.
+    Cmdline(Regex),
+    /// File containing the PID.
+    PidFile(String),
+}
+
+struct Watched {
+    label: String,
+    matcher: Matcher,
+}
+
+impl Watched {
+    /// Parses "[LABEL=]SPEC", where SPEC is "/regex/", a path to a PID file or a name.
+    fn parse(spec: &str) -> Result<Watched, String> {
+        let (label, spec) = match spec.find('=') {
+            Some(i) if !spec.starts_with('/') => (Some(&spec[..i]), &spec[i + 1..]),
+            _ => (None, spec),
+        };
+
+        let (default_label, matcher) = if spec.len() >= 2 && spec.starts_with('/') &&
+                                          spec.ends_with('/') {
+            let re = &spec[1..spec.len() - 1];
+            let regex = Regex::new(re).map_err(|_| format!("invalid regex '{}'", re))?;
+            (String::from(re), Matcher::Cmdline(regex))
+        } else if spec.starts_with('/') || spec.starts_with("~/") {
+            let name = Path::new(spec)
+                           .file_stem()
+                           .and_then(|s| s.to_str())
+                           .unwrap_or(spec);
+            (String::from(name), Matcher::PidFile(expand_home(spec)))
+        } else if !spec.is_empty() {
+            let name: String = spec.chars().take(COMM_LEN).collect();
+            (String::from(spec), Matcher::Name(name))
+        } else {
+            return Err(String::from("empty process specification"));
+        };
+
+        Ok(Watched {
+            label: String::from(label.unwrap_or(&default_label)),
+            matcher: matcher,
+        })
+    }
+}
+
+/// Splits the list of process specifications at commas, except within "/regex/" specs, which
+/// extend to the next unescaped '/' and may contain commas (e.g. "/a{1,3}/"). PID file paths
+/// also start with '/', but end at the first comma after their first component just the same.
+fn split_specs(list: &str) -> Vec<String> {
+    let mut specs = Vec::new();
+    let mut spec = String::new();
+    let mut in_regex = false;
+
+    for c in list.chars() {
+        match c {
+            ',' if !in_regex => specs.push(spec.split_off(0)),
+            '/' if in_regex && !spec.ends_with('\\') => {
+                in_regex = false;
+                spec.push(c);
+            }
+            // A regex starts the spec, after an optional "LABEL=".
+            '/' if spec.is_empty() || spec.ends_with('=') => {
+                in_regex = true;
+                spec.push(c);
+            }
+            _ => spec.push(c),
+        }
+    }
+    specs.push(spec);
+    specs
+}
+
+/// Reads a process' command line with the arguments separated by spaces.
+fn cmdline(pid: i32) -> Option<String> {
+    let raw = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args: Vec<String> = raw.split(|&b| b == 0)
+                               .filter(|a| !a.is_empty())
+                               .map(|a| String::from_utf8_lossy(a).into_owned())
+                               .collect();
+    Some(args.join(" "))
+}
+
+fn read_pidfile(path: &str) -> Option<i32> {
+    read_file(path).and_then(|s| i32::from_str(s.trim()).ok())
+}
+
+/// Returns the indices of the processes matched by m.
+fn matching(m: &Matcher, procs: &[Process], cmdlines: &[Option<String>]) -> Vec<usize> {
+    match *m {
+        Matcher::Name(ref name) => {
+            (0..procs.len()).filter(|&i| procs[i].name == *name).collect()
+        }
+        // Our own command line contains the regex.
+        Matcher::Cmdline(ref re) => {
+            let me = process::id() as i32;
+            (0..procs.len())
+                .filter(|&i| procs[i].pid != me)
+                .filter(|&i| cmdlines[i].as_ref().map(|c| re.is_match(c)).unwrap_or(false))
+                .collect()
+        }
+        Matcher::PidFile(ref path) => {
+            let pid = read_pidfile(path);
+            procs.iter().position(|p| Some(p.pid) == pid).into_iter().collect()
+        }
+    }
+}
+
+struct ProcMetric {
+    watched: Vec<Watched>,
+    fmt: String,
+    usage: CpuUsage,
+    error: Option<String>,
+}
+
+impl ProcMetric {
+    fn configure(&mut self, arg: &str) -> Result<(), String> {
+        let mut parts = arg.splitn(2, ';');
+
+        for spec in split_specs(parts.next().unwrap_or("").trim()) {
+            self.watched.push(Watched::parse(spec.trim())?);
+        }
+        if self.watched.is_empty() {
+            return Err(String::from("no processes given"));
+        }
+        if let Some(fmt) = parts.next() {
+            self.fmt = String::from(fmt);
+        }
+        Ok(())
+    }
+
+    fn needs_cmdlines(&self) -> bool {
+        self.watched.iter().any(|w| match w.matcher {
+            Matcher::Cmdline(_) => true,
+            _ => false,
+        })
+    }
+}
+
+impl Metric for ProcMetric {
+    // arg is a comma-separated list of processes, each "[LABEL=]SPEC" where SPEC is a process
+    // name, a regex on the command line ("/python3? -m http.server/") or the path of a PID file;
+    // optionally followed by ';' and a format for each process with %l for the label, %n for the
+    // number of matching processes, %c for their CPU usage in percent and %m for their RSS:
+    // "sshd,web=/gunicorn: master/,/run/crond.pid;%l"
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        if let Err(e) = self.configure(&arg.unwrap_or(String::new())) {
+            self.error = Some(format!("proc: {}", e));
+            return;
+        }
+        self.usage.update(&procs::scan(), MetricState::now());
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
+
+        let procs = procs::scan();
+        let cpu = self.usage.update(&procs, MetricState::now());
+        let cmdlines: Vec<Option<String>> = if self.needs_cmdlines() {
+            procs.iter().map(|p| cmdline(p.pid)).collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut missing = false;
+        let mut entries = Vec::new();
+        for w in self.watched.iter() {
+            let found = matching(&w.matcher, &procs, &cmdlines);
+            missing |= found.is_empty();
+
+            let mut values = BTreeMap::new();
+            values.insert('l', w.label.clone());
+            values.insert('n', format!("{}", found.len()));
+            values.insert('c', format!("{:.0}", found.iter().fold(0., |sum, &i| sum + cpu[i])));
+            values.insert('m', format_kib(found.iter().map(|&i| procs[i].rss).sum()));
+            entries.push(format_template(&self.fmt, &values));
+        }
+
+        let color = if missing { Color::Red } else { Color::Default };
+        RenderResult::new(entries.join(" "), color).with_urgent(missing)
+    }
+}
+
+pub fn make_proc_metric() -> Box<Metric> {
+    Box::new(ProcMetric {
+        watched: Vec::new(),
+        fmt: String::from(DEFAULT_FMT),
+        usage: CpuUsage::new(),
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let w = Watched::parse("gnome-keyring-daemon").unwrap();
+        assert_eq!(w.label, "gnome-keyring-daemon");
+        assert!(match w.matcher {
+            Matcher::Name(ref n) => n == "gnome-keyring-d",
+            _ => false,
+        });
+
+        let w = Watched::parse("web=/gunicorn: master \\[app=\\w+\\]/").unwrap();
+        assert_eq!(w.label, "web");
+        assert!(match w.matcher {
+            Matcher::Cmdline(ref re) => re.is_match("gunicorn: master [app=shop]"),
+            _ => false,
+        });
+
+        let w = Watched::parse("/run/crond.pid").unwrap();
+        assert_eq!(w.label, "crond");
+        assert!(match w.matcher {
+            Matcher::PidFile(ref p) => p == "/run/crond.pid",
+            _ => false,
+        });
+
+        assert!(Watched::parse("/[/").is_err());
+    }
+
+    #[test]
+    fn test_split_specs() {
+        assert_eq!(split_specs("sshd,x=/a{1,3}/,/run/crond.pid,/foo,bar\\/,baz/"),
+                   vec!["sshd", "x=/a{1,3}/", "/run/crond.pid", "/foo,bar\\/,baz/"]);
+
+        let mut m = ProcMetric {
+            watched: Vec::new(),
+            fmt: String::from(DEFAULT_FMT),
+            usage: CpuUsage::new(),
+            error: None,
+        };
+        m.configure("/python3? -m http.server( [0-9]{2,5})?/,sshd").unwrap();
+        assert_eq!(m.watched.len(), 2);
+        assert!(match m.watched[0].matcher {
+            Matcher::Cmdline(ref re) => re.is_match("python3 -m http.server 8080"),
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_render() {
+        let pidfile = format!("{}/myi3stat-test-{}.pid",
+                              ::std::env::temp_dir().display(),
+                              ::std::process::id());
+        fs::write(&pidfile, format!("{}\n", ::std::process::id())).unwrap();
+
+        let mut metric = make_proc_metric();
+        let mut st = MetricState::new();
+        metric.init(&mut st,
+                    Some(format!("me={},none=/^no such process$/;%l:%n", pidfile)));
+        let result = metric.render(&mut st);
+        assert_eq!(result.get().0, "me:1 none:0");
+        assert!(result.is_urgent());
+
+        let _ = fs::remove_file(&pidfile);
+    }
+}
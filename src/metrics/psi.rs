@@ -0,0 +1,222 @@
+//! Shows Pressure Stall Information (PSI) for CPU, memory and I/O.
+
+use framework::*;
+use helper::{commaseparated_to_vec, read_file, read_procfs_file};
+
+use std::str::FromStr;
+
+const DEFAULT_RESOURCES: &'static str = "cpu,memory,io";
+const DEFAULT_FIELDS: &'static str = "some10";
+
+/// avg10, avg60, avg300 of one line of a pressure file.
+type Averages = [f64; 3];
+
+/// Contents of a pressure file. The "full" line is missing for CPU on older kernels.
+#[derive(Debug, PartialEq)]
+struct Pressure {
+    some: Averages,
+    full: Option<Averages>,
+}
+
+/// A value to be displayed: whether to use the "full" line, and the index of the average.
+type Field = (bool, usize);
+
+/// Parses the contents of a pressure file:
+///
+///     some avg10=0.12 avg60=0.05 avg300=0.01 total=123456
+///     full avg10=0.00 avg60=0.00 avg300=0.00 total=2345
+fn parse_pressure(content: &str) -> Option<Pressure> {
+    let mut some = None;
+    let mut full = None;
+
+    for line in content.lines() {
+        let mut words = line.split_whitespace();
+        let kind = words.next();
+        let mut avgs = [0.; 3];
+
+        for word in words {
+            let mut kv = word.splitn(2, '=');
+            let idx = match kv.next() {
+                Some("avg10") => 0,
+                Some("avg60") => 1,
+                Some("avg300") => 2,
+                _ => continue,
+            };
+            avgs[idx] = kv.next().and_then(|v| f64::from_str(v).ok()).unwrap_or(0.);
+        }
+
+        match kind {
+            Some("some") => some = Some(avgs),
+            Some("full") => full = Some(avgs),
+            _ => (),
+        }
+    }
+
+    some.map(|some| {
+        Pressure {
+            some: some,
+            full: full,
+        }
+    })
+}
+
+fn parse_field(f: &str) -> Option<Field> {
+    let (full, avg) = if f.starts_with("some") {
+        (false, &f[4..])
+    } else if f.starts_with("full") {
+        (true, &f[4..])
+    } else {
+        return None;
+    };
+    match avg {
+        "10" => Some((full, 0)),
+        "60" => Some((full, 1)),
+        "300" => Some((full, 2)),
+        _ => None,
+    }
+}
+
+struct PSIMetric {
+    resources: Vec<String>,
+    fields: Vec<Field>,
+    /// If set, the pressure files of this cgroup (v2) directory are read instead of the
+    /// system-wide ones.
+    cgroup: Option<String>,
+    warn: f64,
+    crit: f64,
+}
+
+impl PSIMetric {
+    fn read_pressure(&self, resource: &str) -> Option<Pressure> {
+        let content = match self.cgroup {
+            Some(ref dir) => read_file(&format!("{}/{}.pressure", dir, resource)),
+            None => read_procfs_file(format!("/pressure/{}", resource)),
+        };
+        content.and_then(|c| parse_pressure(&c))
+    }
+
+    fn get_color(&self, max: f64) -> Color {
+        if max >= self.crit {
+            Color::Red
+        } else if max >= self.warn {
+            Color::Orange
+        } else {
+            Color::Green
+        }
+    }
+
+    /// Formats the value of field; updates max with it.
+    fn format_field(pressure: &Pressure, (full, idx): Field, max: &mut f64) -> String {
+        let avgs = if full { pressure.full } else { Some(pressure.some) };
+
+        match avgs {
+            Some(a) => {
+                *max = max.max(a[idx]);
+                format!("{:.1}", a[idx])
+            }
+            None => String::from("-"),
+        }
+    }
+
+    fn short_name(resource: &str) -> &str {
+        match resource {
+            "memory" => "mem",
+            r => r,
+        }
+    }
+}
+
+impl Metric for PSIMetric {
+    // arg is a comma-separated list of resources, optionally followed by a comma-separated list
+    // of fields ("some" or "full", followed by 10, 60 or 300) and options: cgroup=<directory>,
+    // warn=<percent>, crit=<percent>. Example: "cpu,io some10,full60 warn=5"
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        let arg = arg.unwrap_or(String::new());
+        let mut positional = Vec::new();
+
+        for word in arg.split_whitespace() {
+            if word.starts_with("cgroup=") {
+                self.cgroup = Some(String::from(&word["cgroup=".len()..]));
+            } else if word.starts_with("warn=") {
+                self.warn = f64::from_str(&word["warn=".len()..]).unwrap_or(self.warn);
+            } else if word.starts_with("crit=") {
+                self.crit = f64::from_str(&word["crit=".len()..]).unwrap_or(self.crit);
+            } else {
+                positional.push(word);
+            }
+        }
+
+        let resources = positional.get(0).cloned().unwrap_or(DEFAULT_RESOURCES);
+        let fields = positional.get(1).cloned().unwrap_or(DEFAULT_FIELDS);
+
+        self.resources = commaseparated_to_vec(String::from(resources));
+        self.fields = commaseparated_to_vec(String::from(fields))
+                          .iter()
+                          .filter_map(|f| parse_field(f))
+                          .collect();
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        let mut out = Vec::new();
+        let mut max: f64 = 0.;
+
+        for resource in self.resources.iter() {
+            let pressure = match self.read_pressure(resource) {
+                Some(p) => p,
+                None => continue,
+            };
+
+            let values: Vec<String> = self.fields
+                                          .iter()
+                                          .map(|&f| PSIMetric::format_field(&pressure, f, &mut max))
+                                          .collect();
+            out.push(format!("{}:{}", PSIMetric::short_name(resource), values.join("/")));
+        }
+
+        if out.is_empty() {
+            return RenderResult::new(String::from("psi n/a"), Color::Default);
+        }
+        RenderResult::new(out.join(" "), self.get_color(max))
+    }
+}
+
+pub fn make_psi_metric() -> Box<Metric> {
+    Box::new(PSIMetric {
+        resources: Vec::new(),
+        fields: Vec::new(),
+        cgroup: None,
+        warn: 10.,
+        crit: 40.,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_field, parse_pressure, Pressure};
+
+    #[test]
+    fn test_parse_pressure() {
+        let mem = "some avg10=1.50 avg60=0.75 avg300=0.10 total=123456\nfull avg10=0.20 \
+                   avg60=0.10 avg300=0.00 total=2345\n";
+        assert_eq!(parse_pressure(mem),
+                   Some(Pressure {
+                       some: [1.5, 0.75, 0.1],
+                       full: Some([0.2, 0.1, 0.]),
+                   }));
+
+        let cpu = "some avg10=3.00 avg60=2.00 avg300=1.00 total=42\n";
+        assert_eq!(parse_pressure(cpu),
+                   Some(Pressure {
+                       some: [3., 2., 1.],
+                       full: None,
+                   }));
+        assert_eq!(parse_pressure(""), None);
+    }
+
+    #[test]
+    fn test_parse_field() {
+        assert_eq!(parse_field("some10"), Some((false, 0)));
+        assert_eq!(parse_field("full300"), Some((true, 2)));
+        assert_eq!(parse_field("full5"), None);
+    }
+}
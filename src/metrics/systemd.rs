@@ -0,0 +1,379 @@
+//! Shows the number of failed systemd units of the system and user managers, and the state of
+//! given units. systemd is queried over D-Bus with busctl; if that isn't available, systemctl's
+//! output is parsed instead.
+
+use framework::*;
+use helper::{commaseparated_to_vec, format_template, run_command};
+use json;
+use json::Json;
+use watch::Poller;
+
+use std::collections::BTreeMap;
+
+const DEFAULT_FMT: &'static str = "failed: %f %u";
+/// Time between two queries, in milliseconds; each one runs a few processes, on a thread of its
+/// own. Clicking queries right away.
+const REFRESH_INTERVAL: i64 = 10000;
+
+const DESTINATION: &'static str = "org.freedesktop.systemd1";
+const OBJECT: &'static str = "/org/freedesktop/systemd1";
+const INTERFACE: &'static str = "org.freedesktop.systemd1.Manager";
+
+/// Runs a command and returns its output if it succeeded; replaced by recorded outputs in tests.
+type Runner = fn(&str, &[&str]) -> Option<String>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Manager {
+    System,
+    User,
+}
+
+impl Manager {
+    /// Selects the manager with busctl as well as with systemctl.
+    fn flag(self) -> &'static str {
+        match self {
+            Manager::System => "--system",
+            Manager::User => "--user",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Unit {
+    manager: Manager,
+    /// Full unit name, e.g. "sshd.service".
+    name: String,
+    /// As given, e.g. "sshd".
+    label: String,
+}
+
+/// Parses the reply of ListUnitsFiltered or ListUnitsByNames as printed by "busctl --json=short
+/// call": an array of unit structs (name, description, load, active and sub state, ...).
+/// Returns unit names and active states.
+fn parse_busctl_units(out: &str) -> Option<Vec<(String, String)>> {
+    let reply = json::parse(out).ok()?;
+    let units = reply.get("data")?.as_array()?.first()?.as_array()?;
+    units.iter()
+         .map(|u| {
+             let fields = u.as_array()?;
+             let field = |i: usize| fields.get(i).and_then(Json::as_str).map(String::from);
+             Some((field(0)?, field(3)?))
+         })
+         .collect()
+}
+
+/// Parses the output of "systemctl --failed --plain --no-legend": one unit per line, e.g.
+/// "backup.service loaded failed failed Nightly backup".
+fn parse_systemctl_failed(out: &str) -> Vec<(String, String)> {
+    out.lines()
+       .map(|l| l.trim_start_matches('●').split_whitespace().collect::<Vec<&str>>())
+       .filter(|words| words.len() >= 3)
+       .map(|words| (String::from(words[0]), String::from(words[2])))
+       .collect()
+}
+
+/// Parses the output of "systemctl show --property=Id,ActiveState UNIT...": a block of
+/// "KEY=VALUE" lines per unit, separated by empty lines.
+fn parse_systemctl_show(out: &str) -> Vec<(String, String)> {
+    out.split("\n\n")
+       .filter_map(|block| {
+           let value = |key: &str| {
+               block.lines()
+                    .find(|l| l.starts_with(key) && l[key.len()..].starts_with('='))
+                    .map(|l| String::from(&l[key.len() + 1..]))
+           };
+           Some((value("Id")?, value("ActiveState")?))
+       })
+       .collect()
+}
+
+fn failed_units(run: Runner, m: Manager) -> Option<Vec<(String, String)>> {
+    run("busctl",
+        &[m.flag(), "--json=short", "call", DESTINATION, OBJECT, INTERFACE,
+          "ListUnitsFiltered", "as", "1", "failed"])
+        .and_then(|out| parse_busctl_units(&out))
+        .or_else(|| {
+            run("systemctl", &[m.flag(), "--failed", "--plain", "--no-legend"])
+                .map(|out| parse_systemctl_failed(&out))
+        })
+}
+
+/// Returns the active states of the given units, which are loaded as needed.
+fn unit_states(run: Runner, m: Manager, units: &[&str]) -> Option<Vec<(String, String)>> {
+    let count = format!("{}", units.len());
+    let mut args = vec![m.flag(), "--json=short", "call", DESTINATION, OBJECT, INTERFACE,
+                        "ListUnitsByNames", "as", &count];
+    args.extend_from_slice(units);
+
+    run("busctl", &args)
+        .and_then(|out| parse_busctl_units(&out))
+        .or_else(|| {
+            let mut args = vec![m.flag(), "show", "--property=Id,ActiveState"];
+            args.extend_from_slice(units);
+            run("systemctl", &args).map(|out| parse_systemctl_show(&out))
+        })
+}
+
+#[derive(Clone)]
+struct Status {
+    /// Names of the failed units; None if no manager could be queried.
+    failed: Option<Vec<String>>,
+    /// State of each of the given units; "unknown" if it couldn't be queried.
+    states: Vec<String>,
+}
+
+/// The managers and units to query; moved to the polling thread.
+#[derive(Clone)]
+struct Query {
+    managers: Vec<Manager>,
+    units: Vec<Unit>,
+    run: Runner,
+}
+
+impl Query {
+    fn status(&self) -> Status {
+        let mut failed: Option<Vec<String>> = None;
+        for &m in self.managers.iter() {
+            // The user manager is missing e.g. outside of a login session; that's no error as
+            // long as the other one answers.
+            if let Some(units) = failed_units(self.run, m) {
+                failed.get_or_insert(Vec::new())
+                      .extend(units.into_iter().filter(|u| u.1 == "failed").map(|u| u.0));
+            }
+        }
+
+        let mut states = BTreeMap::new();
+        for &m in [Manager::System, Manager::User].iter() {
+            let names: Vec<&str> = self.units
+                                       .iter()
+                                       .filter(|u| u.manager == m)
+                                       .map(|u| u.name.as_str())
+                                       .collect();
+            if names.is_empty() {
+                continue;
+            }
+            for (name, state) in unit_states(self.run, m, &names).unwrap_or(Vec::new()) {
+                states.insert((m.flag(), name), state);
+            }
+        }
+
+        Status {
+            failed: failed,
+            states: self.units
+                        .iter()
+                        .map(|u| {
+                            states.get(&(u.manager.flag(), u.name.clone()))
+                                  .cloned()
+                                  .unwrap_or(String::from("unknown"))
+                        })
+                        .collect(),
+        }
+    }
+}
+
+struct SystemdMetric {
+    query: Query,
+    fmt: String,
+    /// Set once configured.
+    poller: Option<Poller<Status>>,
+}
+
+impl SystemdMetric {
+    fn configure(&mut self, arg: &str) {
+        let mut parts = arg.splitn(2, ';');
+
+        for word in parts.next().unwrap_or("").split_whitespace() {
+            match word {
+                "system" => self.query.managers.push(Manager::System),
+                "user" => self.query.managers.push(Manager::User),
+                _ => {
+                    for unit in commaseparated_to_vec(String::from(word)) {
+                        let (manager, label) = if unit.starts_with("user:") {
+                            (Manager::User, String::from(&unit[5..]))
+                        } else {
+                            (Manager::System, unit)
+                        };
+                        let name = if label.contains('.') {
+                            label.clone()
+                        } else {
+                            format!("{}.service", label)
+                        };
+                        self.query.units.push(Unit {
+                            manager: manager,
+                            name: name,
+                            label: label,
+                        });
+                    }
+                }
+            }
+        }
+        if self.query.managers.is_empty() {
+            self.query.managers = vec![Manager::System, Manager::User];
+        }
+        if let Some(fmt) = parts.next() {
+            self.fmt = String::from(fmt);
+        }
+    }
+
+    fn start(&mut self, notifier: Option<Notifier>) {
+        let query = self.query.clone();
+        self.poller = Some(Poller::start(move || query.status(), REFRESH_INTERVAL, notifier));
+    }
+}
+
+impl Metric for SystemdMetric {
+    // arg consists of a comma-separated list of units to show the state of ("sshd" means
+    // "sshd.service", "user:NAME" a unit of the user manager) and "system" or "user" to only
+    // count failed units of that manager; optionally followed by ';' and a format with %f for
+    // the number of failed units, %F for their names and %u for the states of the given units:
+    // "sshd,user:syncthing system;%f failed %u"
+    fn init(&mut self, st: &mut MetricState, arg: Option<String>) {
+        self.configure(&arg.unwrap_or(String::new()));
+        self.start(st.notifier());
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        let status = match self.poller.as_ref().and_then(Poller::get) {
+            None => return RenderResult::new(String::new(), Color::Default),
+            Some(s) => s,
+        };
+        let failed = match status.failed {
+            None => return RenderResult::new(String::from("systemd n/a"), Color::Red),
+            Some(ref f) => f,
+        };
+
+        let units: Vec<String> = self.query
+                                     .units
+                                     .iter()
+                                     .zip(status.states.iter())
+                                     .map(|(u, state)| format!("{}:{}", u.label, state))
+                                     .collect();
+        let mut values = BTreeMap::new();
+        values.insert('f', format!("{}", failed.len()));
+        values.insert('F', failed.join(","));
+        values.insert('u', units.join(" "));
+        let text = String::from(format_template(&self.fmt, &values).trim());
+
+        let any_failed = !failed.is_empty() || status.states.iter().any(|s| s == "failed");
+        if any_failed {
+            RenderResult::new(text, Color::Red).with_urgent(true)
+        } else if status.states.iter().any(|s| s != "active") {
+            RenderResult::new(text, Color::Orange)
+        } else {
+            RenderResult::new(text, Color::Default)
+        }
+    }
+
+    // Any click queries systemd again.
+    fn click(&mut self, _: &mut MetricState, _: &ClickEvent) {
+        if let Some(ref poller) = self.poller {
+            poller.refresh();
+        }
+    }
+}
+
+pub fn make_systemd_metric() -> Box<Metric> {
+    Box::new(SystemdMetric {
+        query: Query {
+            managers: Vec::new(),
+            units: Vec::new(),
+            run: run_command,
+        },
+        fmt: String::from(DEFAULT_FMT),
+        poller: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::Duration;
+
+    /// Returns the metric, a state to render it with and wakeups of the first query.
+    fn metric(arg: &str, run: Runner) -> (SystemdMetric, MetricState, Receiver<Wakeup>) {
+        let mut m = SystemdMetric {
+            query: Query {
+                managers: Vec::new(),
+                units: Vec::new(),
+                run: run,
+            },
+            fmt: String::from(DEFAULT_FMT),
+            poller: None,
+        };
+        let (tx, rx) = channel();
+        let mut st = MetricState::with_wakeups(tx);
+        m.init(&mut st, Some(String::from(arg)));
+        (m, st, rx)
+    }
+
+    /// Renders once the first query has finished.
+    fn render(m: &mut SystemdMetric, st: &mut MetricState, rx: &Receiver<Wakeup>) -> RenderResult {
+        assert!(rx.recv_timeout(Duration::from_millis(2000)).is_ok());
+        m.render(st)
+    }
+
+    /// Recorded from busctl; the user manager isn't reachable.
+    fn busctl(cmd: &str, args: &[&str]) -> Option<String> {
+        match (cmd, args[0], args.get(6).cloned()) {
+            ("busctl", "--system", Some("ListUnitsFiltered")) => {
+                Some(String::from(concat!(r#"{"type":"a(ssssssouso)","data":[[["backup.service","#,
+                                          r#""Nightly backup","loaded","failed","failed","","#,
+                                          r#""/org/freedesktop/systemd1/unit/backup_2eservice","#,
+                                          r#"0,"","/"]]]}"#)))
+            }
+            ("busctl", "--system", Some("ListUnitsByNames")) => {
+                assert_eq!(&args[7..], &["as", "2", "sshd.service", "cups.socket"]);
+                Some(String::from(concat!(r#"{"type":"a(ssssssouso)","data":[[["sshd.service","#,
+                                          r#""OpenSSH Daemon","loaded","active","running","","#,
+                                          r#""/org/freedesktop/systemd1/unit/sshd_2eservice","#,
+                                          r#"0,"","/"],["cups.socket","CUPS Scheduler","loaded","#,
+                                          r#""inactive","dead","","#,
+                                          r#""/org/freedesktop/systemd1/unit/cups_2esocket",0,"#,
+                                          r#""","/"]]]}"#)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Recorded from systemctl, for a system without busctl.
+    fn systemctl(cmd: &str, args: &[&str]) -> Option<String> {
+        match (cmd, args[0], args[1]) {
+            ("systemctl", "--system", "--failed") => Some(String::new()),
+            ("systemctl", "--user", "--failed") => {
+                Some(String::from("pipewire.service loaded failed failed PipeWire Multimedia \
+                                   Service\nxdg-desktop-portal.service loaded failed failed \
+                                   Portal service\n"))
+            }
+            ("systemctl", "--user", "show") => {
+                Some(String::from("Id=syncthing.service\nActiveState=active\n"))
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_busctl() {
+        let (mut m, mut st, rx) = metric("sshd,cups.socket;%f %F %u", busctl);
+        let result = render(&mut m, &mut st, &rx);
+        assert_eq!(result.get().0, "1 backup.service sshd:active cups.socket:inactive");
+        assert!(result.is_urgent());
+    }
+
+    #[test]
+    fn test_systemctl() {
+        let (mut m, mut st, rx) = metric("user:syncthing", systemctl);
+        let result = render(&mut m, &mut st, &rx);
+        assert_eq!(result.get().0, "failed: 2 syncthing:active");
+        assert!(result.is_urgent());
+
+        let (mut m, mut st, rx) = metric("system", systemctl);
+        let result = render(&mut m, &mut st, &rx);
+        assert_eq!(result.get().0, "failed: 0");
+        assert!(!result.is_urgent());
+
+        let (mut m, mut st, rx) = metric("", |_, _| None);
+        assert_eq!(render(&mut m, &mut st, &rx).get().0, "systemd n/a");
+    }
+}
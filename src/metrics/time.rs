@@ -1,28 +1,90 @@
 //! A simple clock.
 
 use framework::*;
-
-extern crate time;
-//use self::time;
+use helper::{check_strftime, commaseparated_to_vec};
+use tz::{format_time, LocaleNames, TimeZone};
 
 struct TimeMetric {
     fmt: String,
+    /// Shown instead of fmt after a click.
+    alt_fmt: Option<String>,
+    use_alt: bool,
+    zones: Vec<TimeZone>,
+    names: Option<LocaleNames>,
+    error: Option<String>,
 }
 
 const DEFAULT_FMT: &'static str = "%a %b %d %H:%M:%S %Y";
 
+impl TimeMetric {
+    fn configure(&mut self, arg: &str) -> Result<(), String> {
+        let mut parts = arg.split(';');
+
+        self.fmt = String::from(parts.next().unwrap_or(DEFAULT_FMT));
+        check_strftime(&self.fmt)?;
+
+        self.zones = match parts.next().map(str::trim) {
+            None | Some("") => vec![TimeZone::local()],
+            Some(zones) => {
+                let mut tzs = Vec::new();
+                for zone in commaseparated_to_vec(String::from(zones)) {
+                    tzs.push(TimeZone::load(zone.trim())?);
+                }
+                tzs
+            }
+        };
+
+        if let Some(alt) = parts.next() {
+            check_strftime(alt)?;
+            self.alt_fmt = Some(String::from(alt));
+        }
+        Ok(())
+    }
+}
+
 impl Metric for TimeMetric {
+    // arg is the format, optionally followed by ';' and a comma-separated list of time zones, and
+    // another ';' and an alternative format shown after clicking: "%H:%M;UTC,Asia/Tokyo;%d.%m."
     fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
-        self.fmt = arg.unwrap_or(String::from(DEFAULT_FMT));
+        let arg = arg.unwrap_or(String::from(DEFAULT_FMT));
+
+        if let Err(e) = self.configure(&arg) {
+            self.error = Some(format!("clock: {}", e));
+        }
+        self.names = Some(LocaleNames::from_env());
     }
     fn render(&mut self, _: &mut MetricState) -> RenderResult {
-        let t = time::now();
-        let tstr = format!("{}", t.strftime(&self.fmt).unwrap());
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
 
-        RenderResult::new(tstr, Color::Default)
+        let now = MetricState::now() / 1000;
+        let fmt = match (self.use_alt, &self.alt_fmt) {
+            (true, &Some(ref alt)) => alt,
+            _ => &self.fmt,
+        };
+        let names = self.names.as_ref().unwrap();
+        let times: Vec<String> = self.zones
+                                     .iter()
+                                     .map(|zone| format_time(now, zone, names, fmt))
+                                     .collect();
+
+        RenderResult::new(times.join("  "), Color::Default)
+    }
+    fn click(&mut self, _: &mut MetricState, ev: &ClickEvent) {
+        if ev.button == 1 {
+            self.use_alt = !self.use_alt;
+        }
     }
 }
 
 pub fn clock_metric() -> Box<Metric> {
-    Box::new(TimeMetric { fmt: String::new() })
+    Box::new(TimeMetric {
+        fmt: String::new(),
+        alt_fmt: None,
+        use_alt: false,
+        zones: Vec::new(),
+        names: None,
+        error: None,
+    })
 }
@@ -0,0 +1,333 @@
+//! Countdown to a fixed date, stopwatch and pomodoro timer.
+
+use framework::*;
+use helper::{read_file, state_file_path, write_file};
+use tz::TimeZone;
+
+extern crate chrono;
+use self::chrono::NaiveDateTime;
+
+use std::cmp::max;
+use std::str::FromStr;
+
+/// For how long a block stays urgent after a pomodoro phase has ended, in milliseconds.
+const TRANSITION_URGENT: i64 = 10000;
+
+enum Mode {
+    /// Counts down to a point in time (seconds since the epoch).
+    Countdown { target: i64, label: String },
+    Stopwatch,
+    /// Lengths of work, short break and long break periods in milliseconds.
+    Pomodoro { work: i64, short: i64, long: i64 },
+}
+
+/// A stopwatch that can be paused; all times in milliseconds.
+#[derive(Debug, PartialEq)]
+struct Stopwatch {
+    running: bool,
+    /// Time accumulated before the last start.
+    base: i64,
+    /// When the stopwatch was last started.
+    started_at: i64,
+}
+
+impl Stopwatch {
+    fn new() -> Stopwatch {
+        Stopwatch {
+            running: false,
+            base: 0,
+            started_at: 0,
+        }
+    }
+
+    fn elapsed(&self, now: i64) -> i64 {
+        if self.running {
+            self.base + (now - self.started_at)
+        } else {
+            self.base
+        }
+    }
+
+    fn toggle(&mut self, now: i64) {
+        if self.running {
+            self.base = self.elapsed(now);
+        } else {
+            self.started_at = now;
+        }
+        self.running = !self.running;
+    }
+
+    /// Parses the state file format written by serialize(): "<running> <base> <started_at>".
+    fn parse(content: &str) -> Option<Stopwatch> {
+        let fields: Vec<&str> = content.split_whitespace().collect();
+        if fields.len() != 3 {
+            return None;
+        }
+        match (bool::from_str(fields[0]), i64::from_str(fields[1]), i64::from_str(fields[2])) {
+            (Ok(running), Ok(base), Ok(started_at)) => {
+                Some(Stopwatch {
+                    running: running,
+                    base: base,
+                    started_at: started_at,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn serialize(&self) -> String {
+        format!("{} {} {}\n", self.running, self.base, self.started_at)
+    }
+}
+
+/// Formats a duration in seconds as "MM:SS", "H:MM:SS" or "Nd HH:MM:SS".
+fn format_duration(secs: i64) -> String {
+    let (d, h, m, s) = (secs / 86400, (secs / 3600) % 24, (secs / 60) % 60, secs % 60);
+
+    if d > 0 {
+        format!("{}d {:02}:{:02}:{:02}", d, h, m, s)
+    } else if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{:02}:{:02}", m, s)
+    }
+}
+
+/// Returns the pomodoro phase at the given elapsed time: its name, the time left in it and how
+/// long it has been running. A cycle consists of four work periods, separated by short breaks and
+/// followed by a long break.
+fn pomodoro_phase(elapsed: i64, work: i64, short: i64, long: i64) -> (&'static str, i64, i64) {
+    let phases = [("work", work),
+                  ("break", short),
+                  ("work", work),
+                  ("break", short),
+                  ("work", work),
+                  ("break", short),
+                  ("work", work),
+                  ("long break", long)];
+    let cycle = phases.iter().fold(0, |acc, &(_, len)| acc + len);
+    // Negative if the clock was set back.
+    let mut t = if cycle > 0 { max(elapsed, 0) % cycle } else { 0 };
+
+    for &(name, len) in phases.iter() {
+        if t < len {
+            return (name, len - t, t);
+        }
+        t -= len;
+    }
+    ("work", work, 0)
+}
+
+/// Parses "YYYY-MM-DD HH:MM" in local time into seconds since the epoch.
+fn parse_local_datetime(s: &str, zone: &TimeZone) -> Option<i64> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+        .ok()
+        .map(|dt| zone.utc_from_local(dt.timestamp()))
+}
+
+/// Renders the metric at when (epoch milliseconds) unless that has been done before; scheduled
+/// holds the time of the last such render.
+fn wake_once(st: &MetricState, scheduled: &mut i64, when: i64) {
+    if when != *scheduled {
+        st.wake_at(when);
+        *scheduled = when;
+    }
+}
+
+struct TimerMetric {
+    mode: Mode,
+    watch: Stopwatch,
+    /// Time of the next phase change or of the countdown's end, for which a render is scheduled.
+    wake: i64,
+    error: Option<String>,
+}
+
+impl TimerMetric {
+    fn configure(&mut self, arg: &str) -> Result<(), String> {
+        let words: Vec<&str> = arg.split_whitespace().collect();
+
+        self.mode = match words.first() {
+            Some(&"countdown") if words.len() >= 3 => {
+                let when = format!("{} {}", words[1], words[2]);
+                let target = parse_local_datetime(&when, &TimeZone::local())
+                                 .ok_or(format!("invalid date '{}'", when))?;
+                Mode::Countdown {
+                    target: target,
+                    label: words[3..].join(" "),
+                }
+            }
+            Some(&"countdown") => return Err(String::from("countdown needs YYYY-MM-DD HH:MM")),
+            None | Some(&"stopwatch") => Mode::Stopwatch,
+            Some(&"pomodoro") => {
+                let mut minutes = [25, 5, 15];
+                for (i, w) in words[1..].iter().take(3).enumerate() {
+                    minutes[i] = i64::from_str(w).map_err(|_| format!("invalid length '{}'", w))?;
+                }
+                Mode::Pomodoro {
+                    work: minutes[0] * 60000,
+                    short: minutes[1] * 60000,
+                    long: minutes[2] * 60000,
+                }
+            }
+            Some(m) => return Err(format!("unknown mode '{}'", m)),
+        };
+        Ok(())
+    }
+
+    fn state_file(&self) -> Option<String> {
+        match self.mode {
+            Mode::Countdown { .. } => None,
+            Mode::Stopwatch => state_file_path("timer-stopwatch"),
+            Mode::Pomodoro { .. } => state_file_path("timer-pomodoro"),
+        }
+    }
+
+    fn save(&self) {
+        if let Some(path) = self.state_file() {
+            write_file(&path, &self.watch.serialize());
+        }
+    }
+}
+
+impl Metric for TimerMetric {
+    // arg is one of "countdown YYYY-MM-DD HH:MM [label]", "stopwatch" or
+    // "pomodoro [work [break [long break]]]" with lengths in minutes.
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        if let Err(e) = self.configure(&arg.unwrap_or(String::new())) {
+            self.error = Some(format!("timer: {}", e));
+            return;
+        }
+        if let Some(watch) = self.state_file()
+                                 .and_then(|path| read_file(&path))
+                                 .and_then(|c| Stopwatch::parse(&c)) {
+            self.watch = watch;
+        }
+    }
+
+    fn render(&mut self, st: &mut MetricState) -> RenderResult {
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
+
+        let now = MetricState::now();
+        match self.mode {
+            Mode::Countdown { target, ref label } => {
+                let left = target - now / 1000;
+                if left > 0 {
+                    wake_once(st, &mut self.wake, target * 1000);
+                }
+                let text = if left > 0 { format_duration(left) } else { String::from("now") };
+                let text = if label.is_empty() { text } else { format!("{}: {}", label, text) };
+                RenderResult::new(text, Color::Default).with_urgent(left <= 0)
+            }
+            Mode::Stopwatch => {
+                let text = format_duration(self.watch.elapsed(now) / 1000);
+                let color = if self.watch.running { Color::Green } else { Color::Default };
+                RenderResult::new(text, color)
+            }
+            Mode::Pomodoro { work, short, long } => {
+                let elapsed = self.watch.elapsed(now);
+                let (phase, left, into) = pomodoro_phase(elapsed, work, short, long);
+                // The transition is urgent even if the interval is longer than TRANSITION_URGENT.
+                if self.watch.running {
+                    wake_once(st, &mut self.wake, now + left);
+                }
+                let text = format!("{} {}", phase, format_duration((left + 999) / 1000));
+                let color = match (self.watch.running, phase) {
+                    (false, _) => Color::Default,
+                    (true, "work") => Color::Orange,
+                    (true, _) => Color::Green,
+                };
+                let urgent = self.watch.running && elapsed >= work && into < TRANSITION_URGENT;
+                RenderResult::new(text, color).with_urgent(urgent)
+            }
+        }
+    }
+
+    // Button 1 starts and stops the stopwatch or pomodoro timer, button 3 resets it.
+    fn click(&mut self, _: &mut MetricState, ev: &ClickEvent) {
+        if let Mode::Countdown { .. } = self.mode {
+            return;
+        }
+        match ev.button {
+            1 => self.watch.toggle(MetricState::now()),
+            3 => self.watch = Stopwatch::new(),
+            _ => return,
+        }
+        self.save();
+    }
+}
+
+pub fn make_timer_metric() -> Box<Metric> {
+    Box::new(TimerMetric {
+        mode: Mode::Stopwatch,
+        watch: Stopwatch::new(),
+        wake: 0,
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, parse_local_datetime, pomodoro_phase, wake_once, Stopwatch};
+    use framework::MetricState;
+    use tz::TimeZone;
+
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(65), "01:05");
+        assert_eq!(format_duration(3 * 3600 + 65), "3:01:05");
+        assert_eq!(format_duration(2 * 86400 + 3 * 3600 + 65), "2d 03:01:05");
+    }
+
+    #[test]
+    fn test_pomodoro_phase() {
+        let (w, s, l) = (25, 5, 15);
+        assert_eq!(pomodoro_phase(0, w, s, l), ("work", 25, 0));
+        assert_eq!(pomodoro_phase(27, w, s, l), ("break", 3, 2));
+        assert_eq!(pomodoro_phase(30, w, s, l), ("work", 25, 0));
+        assert_eq!(pomodoro_phase(4 * 25 + 3 * 5 + 1, w, s, l), ("long break", 14, 1));
+        // next cycle
+        assert_eq!(pomodoro_phase(4 * 25 + 3 * 5 + 15 + 1, w, s, l), ("work", 24, 1));
+        // clock set back
+        assert_eq!(pomodoro_phase(-7, w, s, l), ("work", 25, 0));
+    }
+
+    #[test]
+    fn test_stopwatch() {
+        let mut sw = Stopwatch::new();
+        sw.toggle(1000);
+        assert_eq!(sw.elapsed(3000), 2000);
+        sw.toggle(4000);
+        assert_eq!(sw.elapsed(10000), 3000);
+        sw.toggle(20000);
+        assert_eq!(sw.elapsed(21000), 4000);
+
+        assert_eq!(Stopwatch::parse(&sw.serialize()), Some(sw));
+        assert_eq!(Stopwatch::parse("garbage"), None);
+    }
+
+    #[test]
+    fn test_parse_local_datetime() {
+        assert_eq!(parse_local_datetime("2017-01-01 00:00", &TimeZone::utc()),
+                   Some(1483228800));
+        assert_eq!(parse_local_datetime("2017-01-01", &TimeZone::utc()), None);
+    }
+
+    #[test]
+    fn test_wake_once() {
+        let (tx, rx) = channel();
+        let st = MetricState::with_wakeups(tx);
+        let mut scheduled = 0;
+        let when = MetricState::now() + 50;
+
+        wake_once(&st, &mut scheduled, when);
+        wake_once(&st, &mut scheduled, when);
+        assert_eq!(scheduled, when);
+        assert!(rx.recv_timeout(Duration::from_millis(2000)).is_ok());
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Shows the processes using the most CPU or memory. Only /proc/[pid]/stat is read for each
+//! process; it contains the name, CPU times and RSS, so a scan costs one read per process.
+
+use framework::*;
+use helper::{format_kib, format_template, truncate};
+use procs;
+use procs::{CpuUsage, Process};
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+const DEFAULT_COUNT: usize = 3;
+const DEFAULT_LEN: usize = 12;
+const DEFAULT_CPU_FMT: &'static str = "%n %c%%";
+const DEFAULT_MEM_FMT: &'static str = "%n %m";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Sort {
+    Cpu,
+    Mem,
+}
+
+struct TopMetric {
+    sort: Sort,
+    count: usize,
+    /// Maximum length of names in characters.
+    max_len: usize,
+    fmt: Option<String>,
+    usage: CpuUsage,
+    error: Option<String>,
+}
+
+impl TopMetric {
+    fn configure(&mut self, arg: &str) -> Result<(), String> {
+        let mut parts = arg.splitn(2, ';');
+
+        for word in parts.next().unwrap_or("").split_whitespace() {
+            let mut kv = word.splitn(2, '=');
+            match (kv.next().unwrap_or(""), kv.next()) {
+                ("cpu", None) => self.sort = Sort::Cpu,
+                ("mem", None) => self.sort = Sort::Mem,
+                ("len", Some(n)) => {
+                    self.max_len = usize::from_str(n).map_err(|_| format!("invalid len '{}'", n))?
+                }
+                (n, None) if usize::from_str(n).is_ok() => self.count = usize::from_str(n).unwrap(),
+                _ => return Err(format!("unknown option '{}'", word)),
+            }
+        }
+        self.fmt = parts.next().map(String::from);
+        Ok(())
+    }
+
+    /// Formats the first processes in the sort order; cpu holds the usage of each process.
+    fn entries(&self, procs: &[Process], cpu: Vec<f64>) -> Vec<String> {
+        let mut top: Vec<(&Process, f64)> = procs.iter().zip(cpu.into_iter()).collect();
+        match self.sort {
+            Sort::Cpu => top.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap()),
+            Sort::Mem => top.sort_by(|a, b| b.0.rss.cmp(&a.0.rss)),
+        }
+
+        let fmt = self.fmt.clone().unwrap_or(String::from(match self.sort {
+            Sort::Cpu => DEFAULT_CPU_FMT,
+            Sort::Mem => DEFAULT_MEM_FMT,
+        }));
+        top.into_iter()
+           .take(self.count)
+           .map(|(p, cpu)| {
+               let mut values = BTreeMap::new();
+               values.insert('n', truncate(&p.name, self.max_len));
+               values.insert('p', format!("{}", p.pid));
+               values.insert('c', format!("{:.0}", cpu));
+               values.insert('m', format_kib(p.rss));
+               format_template(&fmt, &values)
+           })
+           .collect()
+    }
+}
+
+impl Metric for TopMetric {
+    // arg consists of the number of processes to show (default 3), "cpu" or "mem" (sort order,
+    // default cpu) and "len=N" (maximum name length, 0 for no limit); optionally followed by ';'
+    // and a format for each process with %n for the name, %p for the PID, %c for the CPU usage
+    // in percent of one core and %m for the RSS: "5 mem len=8;%n:%m"
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        if let Err(e) = self.configure(&arg.unwrap_or(String::new())) {
+            self.error = Some(format!("top: {}", e));
+            return;
+        }
+        // Also when sorting by memory, for %c.
+        self.usage.update(&procs::scan(), MetricState::now());
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
+
+        let procs = procs::scan();
+        let cpu = self.usage.update(&procs, MetricState::now());
+        let entries = self.entries(&procs, cpu);
+        RenderResult::new(entries.join(" "), Color::Default)
+    }
+
+    fn reset(&mut self, _: &mut MetricState) {
+        self.usage.update(&procs::scan(), MetricState::now());
+    }
+}
+
+pub fn make_top_metric() -> Box<Metric> {
+    Box::new(TopMetric {
+        sort: Sort::Cpu,
+        count: DEFAULT_COUNT,
+        max_len: DEFAULT_LEN,
+        fmt: None,
+        usage: CpuUsage::new(),
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn top(arg: &str) -> TopMetric {
+        let mut top = TopMetric {
+            sort: Sort::Cpu,
+            count: DEFAULT_COUNT,
+            max_len: DEFAULT_LEN,
+            fmt: None,
+            usage: CpuUsage::new(),
+            error: None,
+        };
+        top.configure(arg).unwrap();
+        top
+    }
+
+    fn processes() -> Vec<Process> {
+        [(1, "systemd", 12288), (812, "firefox-esr", 2097152), (4711, "cargo", 524288),
+         (5120, "rust-analyzer-proc-macro-srv", 1572864)]
+            .iter()
+            .map(|&(pid, name, rss)| {
+                Process {
+                    pid: pid,
+                    name: String::from(name),
+                    ticks: 0,
+                    rss: rss,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_configure() {
+        let t = top("5 mem len=8;%n:%m");
+        assert!(t.sort == Sort::Mem);
+        assert_eq!(t.count, 5);
+        assert_eq!(t.max_len, 8);
+        assert_eq!(t.fmt, Some(String::from("%n:%m")));
+
+        let mut t = top("");
+        assert!(t.sort == Sort::Cpu && t.count == DEFAULT_COUNT && t.fmt.is_none());
+        assert_eq!(t.configure("len=x"), Err(String::from("invalid len 'x'")));
+        assert_eq!(t.configure("memory"), Err(String::from("unknown option 'memory'")));
+    }
+
+    #[test]
+    fn test_entries() {
+        let procs = processes();
+        let cpu = vec![0., 35.2, 99.6, 4.];
+
+        assert_eq!(top("").entries(&procs, cpu.clone()),
+                   vec!["cargo 100%", "firefox-esr 35%", "rust-analyz… 4%"]);
+        assert_eq!(top("2 mem len=0").entries(&procs, cpu.clone()),
+                   vec!["firefox-esr 2.0G", "rust-analyzer-proc-macro-srv 1.5G"]);
+        // CPU usage is known when sorting by memory, too.
+        assert_eq!(top("1 mem len=4;%p %n %c").entries(&procs, cpu), vec!["812 fir… 35"]);
+    }
+}
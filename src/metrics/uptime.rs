@@ -0,0 +1,121 @@
+//! Shows the system uptime and boot time.
+
+use framework::*;
+use helper::{check_strftime, format_template, number_of_cores, read_procfs_file};
+use tz::{format_time, LocaleNames, TimeZone};
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+const DEFAULT_FMT: &'static str = "%dd %hh";
+const DEFAULT_BOOT_FMT: &'static str = "%Y-%m-%d %H:%M";
+
+struct UptimeMetric {
+    fmt: String,
+    /// Format of the boot time (%b), in strftime syntax.
+    boot_fmt: String,
+    ncpu: i32,
+    zone: Option<TimeZone>,
+    names: Option<LocaleNames>,
+    error: Option<String>,
+}
+
+/// Parses /proc/uptime: seconds since boot, and idle seconds summed over all cores.
+fn parse_uptime(content: &str) -> Option<(f64, f64)> {
+    let mut fields = content.split_whitespace().map(|f| f64::from_str(f).ok());
+
+    match (fields.next(), fields.next()) {
+        (Some(Some(up)), Some(Some(idle))) => Some((up, idle)),
+        _ => None,
+    }
+}
+
+/// Fills in the duration placeholders: %d days, %h hours of the day, %m minutes of the hour
+/// (zero-padded), %s seconds of the minute (zero-padded), %H total hours, %M total minutes.
+fn duration_values(secs: u64, values: &mut BTreeMap<char, String>) {
+    values.insert('d', format!("{}", secs / 86400));
+    values.insert('h', format!("{}", (secs / 3600) % 24));
+    values.insert('m', format!("{:02}", (secs / 60) % 60));
+    values.insert('s', format!("{:02}", secs % 60));
+    values.insert('H', format!("{}", secs / 3600));
+    values.insert('M', format!("{}", secs / 60));
+}
+
+impl Metric for UptimeMetric {
+    // arg is the format (see duration_values(); additionally %i is the idle ratio in percent and
+    // %b the boot time), optionally followed by ';' and the strftime format for the boot time:
+    // "up %H:%m, booted %b;%a %H:%M"
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        let arg = arg.unwrap_or(String::from(DEFAULT_FMT));
+        let mut parts = arg.splitn(2, ';');
+
+        self.fmt = String::from(parts.next().unwrap_or(DEFAULT_FMT));
+        if let Some(boot_fmt) = parts.next() {
+            self.boot_fmt = String::from(boot_fmt);
+        }
+        if let Err(e) = check_strftime(&self.boot_fmt) {
+            self.error = Some(e);
+        }
+        self.ncpu = number_of_cores();
+        self.zone = Some(TimeZone::local());
+        self.names = Some(LocaleNames::from_env());
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        if let Some(ref e) = self.error {
+            return RenderResult::new(format!("uptime: {}", e), Color::Red);
+        }
+
+        let (up, idle) = match read_procfs_file(String::from("/uptime"))
+                                   .and_then(|c| parse_uptime(&c)) {
+            None => return RenderResult::new(String::from("uptime n/a"), Color::Red),
+            Some(u) => u,
+        };
+
+        let mut values = BTreeMap::new();
+        duration_values(up as u64, &mut values);
+        values.insert('i', format!("{:.0}", 100. * idle / (up * self.ncpu as f64)));
+
+        if let (Some(zone), Some(names)) = (self.zone.as_ref(), self.names.as_ref()) {
+            let boot = MetricState::now() / 1000 - up as i64;
+            values.insert('b', format_time(boot, zone, names, &self.boot_fmt));
+        }
+
+        RenderResult::new(format_template(&self.fmt, &values), Color::Default)
+    }
+}
+
+pub fn make_uptime_metric() -> Box<Metric> {
+    Box::new(UptimeMetric {
+        fmt: String::from(DEFAULT_FMT),
+        boot_fmt: String::from(DEFAULT_BOOT_FMT),
+        ncpu: 1,
+        zone: None,
+        names: None,
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{duration_values, parse_uptime};
+    use helper::format_template;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_parse_uptime() {
+        assert_eq!(parse_uptime("350735.47 234388.90\n"), Some((350735.47, 234388.90)));
+        assert_eq!(parse_uptime(""), None);
+    }
+
+    #[test]
+    fn test_duration_values() {
+        let mut values = BTreeMap::new();
+        // 3 days, 4 hours, 5 minutes, 6 seconds
+        duration_values(3 * 86400 + 4 * 3600 + 5 * 60 + 6, &mut values);
+
+        assert_eq!(format_template("%dd %hh", &values), "3d 4h");
+        assert_eq!(format_template("%H:%m:%s", &values), "76:05:06");
+        assert_eq!(format_template("%Mmin", &values), "4565min");
+    }
+}
@@ -0,0 +1,269 @@
+//! Shows the master volume and mute state. Talks to PulseAudio (or PipeWire's PulseAudio server)
+//! through pactl, or to ALSA through amixer. Scrolling changes the volume, clicking toggles mute.
+
+use framework::*;
+use helper::{format_template, run_command};
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+const DEFAULT_FMT: &'static str = "vol %v%%";
+const DEFAULT_STEP: u32 = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Backend {
+    /// pactl with a sink name.
+    Pulse(String),
+    /// amixer with a simple mixer control.
+    Alsa(String),
+}
+
+/// Averages all percentages ("60%") in s; pactl and amixer list one per channel.
+fn average_percent(s: &str) -> Option<u32> {
+    let values: Vec<u32> = s.split(|c: char| c.is_whitespace() || c == '[' || c == ']' || c == '/')
+                            .filter(|w| w.ends_with('%'))
+                            .filter_map(|w| u32::from_str(w.trim_end_matches('%')).ok())
+                            .collect();
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<u32>() / values.len() as u32)
+}
+
+/// Parses the output of "pactl get-sink-volume" and "pactl get-sink-mute".
+fn parse_pactl(volume: &str, mute: &str) -> Option<(u32, bool)> {
+    let first = volume.lines().next().unwrap_or("");
+    Some((average_percent(first)?, mute.trim() == "Mute: yes"))
+}
+
+/// Parses the output of "amixer get CONTROL": "Front Left: Playback 39321 [60%] [-13.31dB] [on]".
+fn parse_amixer(out: &str) -> Option<(u32, bool)> {
+    let channels: Vec<&str> = out.lines()
+                                 .filter(|l| l.contains("Playback") && l.contains("%]"))
+                                 .collect();
+    let volume = average_percent(&channels.join(" "))?;
+    // Muted if all channels are switched off.
+    let muted = channels.iter().all(|l| l.contains("[off]"));
+    Some((volume, muted))
+}
+
+impl Backend {
+    fn detect(device: Option<String>) -> Backend {
+        if run_command("pactl", &["info"]).is_some() {
+            Backend::Pulse(device.unwrap_or(String::from("@DEFAULT_SINK@")))
+        } else {
+            Backend::Alsa(device.unwrap_or(String::from("Master")))
+        }
+    }
+
+    fn read(&self) -> Option<(u32, bool)> {
+        match *self {
+            Backend::Pulse(ref sink) => {
+                let volume = run_command("pactl", &["get-sink-volume", sink])?;
+                let mute = run_command("pactl", &["get-sink-mute", sink])?;
+                parse_pactl(&volume, &mute)
+            }
+            Backend::Alsa(ref control) => {
+                run_command("amixer", &["-M", "get", control]).and_then(|o| parse_amixer(&o))
+            }
+        }
+    }
+
+    /// Changes the volume by delta percent.
+    fn change_volume(&self, delta: i32) {
+        let (sign, abs) = if delta < 0 { ("-", -delta) } else { ("+", delta) };
+        match *self {
+            Backend::Pulse(ref sink) => {
+                run_command("pactl",
+                            &["set-sink-volume", sink, &format!("{}{}%", sign, abs)])
+            }
+            Backend::Alsa(ref control) => {
+                run_command("amixer",
+                            &["-q", "-M", "set", control, &format!("{}%{}", abs, sign)])
+            }
+        };
+    }
+
+    fn toggle_mute(&self) {
+        match *self {
+            Backend::Pulse(ref sink) => run_command("pactl", &["set-sink-mute", sink, "toggle"]),
+            Backend::Alsa(ref control) => run_command("amixer", &["-q", "set", control, "toggle"]),
+        };
+    }
+
+    /// Starts "pactl subscribe", which reports changes as they happen, and sets changed (and
+    /// wakes up the render loop) for every change of a sink or the server (default sink).
+    fn subscribe(&self, changed: Arc<AtomicBool>, notifier: Notifier) -> Option<Child> {
+        if let Backend::Alsa(_) = *self {
+            return None;
+        }
+        let mut child = Command::new("pactl")
+                            .arg("subscribe")
+                            .stdout(Stdio::piped())
+                            .stderr(Stdio::null())
+                            .spawn()
+                            .ok()?;
+        let stdout = child.stdout.take()?;
+
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Err(_) => return,
+                    Ok(l) => l,
+                };
+                if line.contains(" on sink ") || line.contains(" on server") {
+                    changed.store(true, Ordering::SeqCst);
+                    notifier.notify();
+                }
+            }
+        });
+        Some(child)
+    }
+}
+
+struct VolumeMetric {
+    backend: Backend,
+    fmt: String,
+    step: u32,
+    muted_color: Color,
+    /// Set when the volume must be read again; always set if there is no subscription.
+    changed: Arc<AtomicBool>,
+    subscription: Option<Child>,
+    last: Option<(u32, bool)>,
+    error: Option<String>,
+}
+
+impl VolumeMetric {
+    fn configure(&mut self, arg: &str) -> Result<(), String> {
+        let mut parts = arg.splitn(2, ';');
+        let mut backend = None;
+        let mut device = None;
+
+        for word in parts.next().unwrap_or("").split_whitespace() {
+            let mut kv = word.splitn(2, '=');
+            match (kv.next().unwrap_or(""), kv.next()) {
+                ("pulse", None) => backend = Some("pulse"),
+                ("alsa", None) => backend = Some("alsa"),
+                ("device", Some(d)) => device = Some(String::from(d)),
+                ("step", Some(n)) => {
+                    self.step = u32::from_str(n).map_err(|_| format!("invalid step '{}'", n))?
+                }
+                ("muted", Some(c)) => {
+                    self.muted_color = Color::parse(c).ok_or(format!("invalid color '{}'", c))?
+                }
+                _ => return Err(format!("unknown option '{}'", word)),
+            }
+        }
+        if let Some(fmt) = parts.next() {
+            self.fmt = String::from(fmt);
+        }
+
+        self.backend = match backend {
+            Some("pulse") => Backend::Pulse(device.unwrap_or(String::from("@DEFAULT_SINK@"))),
+            Some(_) => Backend::Alsa(device.unwrap_or(String::from("Master"))),
+            None => Backend::detect(device),
+        };
+        Ok(())
+    }
+}
+
+impl Metric for VolumeMetric {
+    // arg consists of options: "pulse" or "alsa" (detected if omitted), "device=NAME" (sink or
+    // mixer control), "step=N" (percent per scroll step), "muted=COLOR"; optionally followed by
+    // ';' and a format with %v for the volume: "pulse step=2 muted=#888888;♪ %v%%"
+    fn init(&mut self, st: &mut MetricState, arg: Option<String>) {
+        if let Err(e) = self.configure(&arg.unwrap_or(String::new())) {
+            self.error = Some(format!("volume: {}", e));
+            return;
+        }
+        if let Some(notifier) = st.notifier() {
+            self.subscription = self.backend.subscribe(self.changed.clone(), notifier);
+        }
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        if let Some(ref e) = self.error {
+            return RenderResult::new(e.clone(), Color::Red);
+        }
+
+        if self.subscription.is_none() || self.changed.swap(false, Ordering::SeqCst) ||
+           self.last.is_none() {
+            self.last = self.backend.read();
+        }
+        match self.last {
+            None => RenderResult::new(String::from("volume n/a"), Color::Red),
+            Some((volume, muted)) => {
+                let mut values = BTreeMap::new();
+                values.insert('v', format!("{}", volume));
+                let color = if muted { self.muted_color.clone() } else { Color::Default };
+                RenderResult::new(format_template(&self.fmt, &values), color)
+            }
+        }
+    }
+
+    // Button 1 toggles mute, scrolling up and down (buttons 4 and 5) changes the volume.
+    fn click(&mut self, _: &mut MetricState, ev: &ClickEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        match ev.button {
+            1 => self.backend.toggle_mute(),
+            4 => self.backend.change_volume(self.step as i32),
+            5 => self.backend.change_volume(-(self.step as i32)),
+            _ => return,
+        }
+        self.changed.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for VolumeMetric {
+    fn drop(&mut self) {
+        if let Some(ref mut child) = self.subscription {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+pub fn make_volume_metric() -> Box<Metric> {
+    Box::new(VolumeMetric {
+        backend: Backend::Alsa(String::from("Master")),
+        fmt: String::from(DEFAULT_FMT),
+        step: DEFAULT_STEP,
+        muted_color: Color::Purple,
+        changed: Arc::new(AtomicBool::new(true)),
+        subscription: None,
+        last: None,
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_amixer, parse_pactl};
+
+    #[test]
+    fn test_parse_pactl() {
+        let volume = "Volume: front-left: 39321 /  60% / -13.31 dB,   front-right: 45875 /  70% / \
+                      -9.29 dB\n        balance 0.14\n";
+        assert_eq!(parse_pactl(volume, "Mute: no\n"), Some((65, false)));
+        assert_eq!(parse_pactl(volume, "Mute: yes\n"), Some((65, true)));
+        assert_eq!(parse_pactl("", "Mute: no\n"), None);
+    }
+
+    #[test]
+    fn test_parse_amixer() {
+        let out = "Simple mixer control 'Master',0\n  Capabilities: pvolume pswitch \
+                   pswitch-joined\n  Playback channels: Front Left - Front Right\n  Limits: \
+                   Playback 0 - 65536\n  Mono:\n  Front Left: Playback 39321 [60%] [on]\n  \
+                   Front Right: Playback 39321 [60%] [on]\n";
+        assert_eq!(parse_amixer(out), Some((60, false)));
+        assert_eq!(parse_amixer(&out.replace("[on]", "[off]")), Some((60, true)));
+        assert_eq!(parse_amixer("amixer: Unable to find simple control 'Foo',0\n"), None);
+    }
+}
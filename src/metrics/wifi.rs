@@ -0,0 +1,214 @@
+//! Shows SSID, link quality and bitrate of a wireless interface.
+
+use framework::*;
+use helper::{format_template, read_procfs_file, run_command};
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+const DEFAULT_FMT: &'static str = "%s %q%% %b";
+
+/// A line of /proc/net/wireless.
+#[derive(Debug, PartialEq)]
+struct WirelessStat {
+    interface: String,
+    /// Link quality; usually out of 70.
+    link: f64,
+    /// Signal level in dBm.
+    level: f64,
+}
+
+/// Link information as reported by `iw dev <interface> link`.
+#[derive(Debug, PartialEq)]
+struct LinkInfo {
+    ssid: String,
+    /// Transmit bitrate in MBit/s.
+    bitrate: Option<f64>,
+}
+
+/// Parses the contents of /proc/net/wireless:
+///
+///     Inter-| sta-|   Quality        |   Discarded packets               | Missed | WE
+///      face | tus | link level noise |  nwid  crypt   frag  retry   misc | beacon | 22
+///     wlp3s0: 0000   70.  -40.  -256        0      0      0      0      0        0
+fn parse_proc_net_wireless(content: &str) -> Vec<WirelessStat> {
+    let mut stats = Vec::new();
+
+    for line in content.lines().skip(2) {
+        let mut parts = line.splitn(2, ':');
+        let interface = parts.next().unwrap_or("").trim();
+        let fields: Vec<&str> = parts.next().unwrap_or("").split_whitespace().collect();
+
+        if interface.is_empty() || fields.len() < 3 {
+            continue;
+        }
+
+        let link = f64::from_str(fields[1].trim_end_matches('.'));
+        let level = f64::from_str(fields[2].trim_end_matches('.'));
+
+        if let (Ok(link), Ok(level)) = (link, level) {
+            stats.push(WirelessStat {
+                interface: String::from(interface),
+                link: link,
+                level: level,
+            });
+        }
+    }
+    stats
+}
+
+/// Parses the output of `iw dev <interface> link`; returns None if not connected.
+fn parse_iw_link(output: &str) -> Option<LinkInfo> {
+    if !output.starts_with("Connected") {
+        return None;
+    }
+
+    let mut info = LinkInfo {
+        ssid: String::new(),
+        bitrate: None,
+    };
+
+    for line in output.lines().map(str::trim) {
+        if line.starts_with("SSID:") {
+            info.ssid = String::from(line["SSID:".len()..].trim());
+        } else if line.starts_with("tx bitrate:") {
+            info.bitrate = line["tx bitrate:".len()..]
+                               .split_whitespace()
+                               .next()
+                               .and_then(|b| f64::from_str(b).ok());
+        }
+    }
+    Some(info)
+}
+
+struct WifiMetric {
+    /// If None, the first interface listed in /proc/net/wireless is used.
+    interface: Option<String>,
+    fmt: String,
+}
+
+impl WifiMetric {
+    fn quality_percent(link: f64) -> f64 {
+        (100. * link / 70.).min(100.).max(0.)
+    }
+
+    fn get_color(quality: f64) -> Color {
+        if quality < 40. {
+            Color::Orange
+        } else {
+            Color::Green
+        }
+    }
+}
+
+impl Metric for WifiMetric {
+    // arg is the interface name, optionally followed by whitespace and a format string:
+    // "wlan0 %s %q%%". %i is replaced by the interface name, %s by the SSID, %q by the link
+    // quality in percent, %l by the signal level in dBm and %b by the bitrate.
+    fn init(&mut self, _: &mut MetricState, arg: Option<String>) {
+        let arg = arg.unwrap_or(String::new());
+        let arg = arg.trim();
+        let (interface, fmt) = match arg.find(char::is_whitespace) {
+            None => (arg, DEFAULT_FMT),
+            Some(i) => (&arg[..i], arg[i..].trim()),
+        };
+
+        if !interface.is_empty() {
+            self.interface = Some(String::from(interface));
+        }
+        self.fmt = String::from(fmt);
+    }
+
+    fn render(&mut self, _: &mut MetricState) -> RenderResult {
+        let stats = parse_proc_net_wireless(&read_procfs_file(String::from("/net/wireless"))
+                                                 .unwrap_or(String::new()));
+        let interface = match self.interface {
+            Some(ref i) => i.clone(),
+            None => {
+                match stats.first() {
+                    Some(st) => st.interface.clone(),
+                    None => return RenderResult::new(String::from("wifi: n/a"), Color::Red),
+                }
+            }
+        };
+
+        let stat = match stats.into_iter().find(|st| st.interface == interface) {
+            Some(st) => st,
+            None => return RenderResult::new(format!("{} down", interface), Color::Red),
+        };
+        let link = match run_command("iw", &["dev", &interface, "link"])
+                             .and_then(|out| parse_iw_link(&out)) {
+            Some(l) => l,
+            None => return RenderResult::new(format!("{} disconnected", interface), Color::Red),
+        };
+
+        let quality = WifiMetric::quality_percent(stat.link);
+        let mut values = BTreeMap::new();
+        values.insert('i', interface);
+        values.insert('s', link.ssid);
+        values.insert('q', format!("{:.0}", quality));
+        values.insert('l', format!("{:.0}", stat.level));
+        values.insert('b',
+                      link.bitrate.map_or(String::from("?"), |b| format!("{}Mb/s", b as u64)));
+
+        RenderResult::new(format_template(&self.fmt, &values),
+                          WifiMetric::get_color(quality))
+    }
+}
+
+pub fn make_wifi_metric() -> Box<Metric> {
+    Box::new(WifiMetric {
+        interface: None,
+        fmt: String::from(DEFAULT_FMT),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_iw_link, parse_proc_net_wireless, LinkInfo, WirelessStat};
+
+    const PROC_NET_WIRELESS: &'static str = "Inter-| sta-|   Quality        |   Discarded \
+                                             packets               | Missed | WE
+ face | tus | link level noise |  nwid  crypt   frag  retry   misc | beacon | 22
+wlp3s0: 0000   54.  -56.  -256        0      0      0      0     12        0
+wlan1-1: 0000   0   0   0        0      0      0      0      0        0
+";
+
+    const IW_LINK: &'static str = "Connected to 00:11:22:33:44:55 (on wlp3s0)
+\tSSID: home net
+\tfreq: 5180
+\tRX: 1093838 bytes (3920 packets)
+\tTX: 105930 bytes (669 packets)
+\tsignal: -56 dBm
+\trx bitrate: 650.0 MBit/s VHT-MCS 7 80MHz short GI VHT-NSS 2
+\ttx bitrate: 866.7 MBit/s VHT-MCS 9 80MHz short GI VHT-NSS 2
+";
+
+    #[test]
+    fn test_parse_proc_net_wireless() {
+        let stats = parse_proc_net_wireless(PROC_NET_WIRELESS);
+
+        assert_eq!(stats,
+                   vec![WirelessStat {
+                            interface: String::from("wlp3s0"),
+                            link: 54.,
+                            level: -56.,
+                        },
+                        WirelessStat {
+                            interface: String::from("wlan1-1"),
+                            link: 0.,
+                            level: 0.,
+                        }]);
+        assert_eq!(parse_proc_net_wireless(""), vec![]);
+    }
+
+    #[test]
+    fn test_parse_iw_link() {
+        assert_eq!(parse_iw_link(IW_LINK),
+                   Some(LinkInfo {
+                       ssid: String::from("home net"),
+                       bitrate: Some(866.7),
+                   }));
+        assert_eq!(parse_iw_link("Not connected.\n"), None);
+    }
+}
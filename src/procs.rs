@@ -0,0 +1,151 @@
+//! Scanning of the processes in /proc, and their CPU usage between scans.
+
+extern crate libc;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::str::FromStr;
+
+pub struct Process {
+    pub pid: i32,
+    /// The command name (comm), at most 15 characters.
+    pub name: String,
+    /// User and system time in clock ticks.
+    pub ticks: u64,
+    /// Resident set size in KiB.
+    pub rss: u64,
+}
+
+/// Parses /proc/[pid]/stat. The name is in parentheses and may itself contain spaces and
+/// parentheses, so the other fields are counted from the last ')'.
+fn parse_stat(pid: i32, stat: &str, page_kib: u64) -> Option<Process> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    if close < open {
+        return None;
+    }
+    // Starts with field 3 (state); utime, stime and rss are fields 14, 15 and 24.
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    let field = |n: usize| fields.get(n - 3).and_then(|f| u64::from_str(f).ok());
+
+    Some(Process {
+        pid: pid,
+        name: String::from(&stat[open + 1..close]),
+        ticks: field(14)? + field(15)?,
+        rss: field(24)? * page_kib,
+    })
+}
+
+/// Returns the IDs of all processes.
+pub fn pids() -> Vec<i32> {
+    match fs::read_dir("/proc") {
+        Err(_) => Vec::new(),
+        Ok(entries) => {
+            entries.filter_map(|e| e.ok())
+                   .filter_map(|e| e.file_name().to_str().and_then(|n| i32::from_str(n).ok()))
+                   .collect()
+        }
+    }
+}
+
+/// Reads a process' stat file into buf (reused across calls, as there may be thousands of them).
+/// None if the process has exited in the meantime.
+pub fn read_process(pid: i32, buf: &mut String) -> Option<Process> {
+    let page_kib = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64 / 1024;
+    buf.clear();
+    fs::File::open(format!("/proc/{}/stat", pid))
+        .and_then(|mut f| f.read_to_string(buf))
+        .ok()?;
+    parse_stat(pid, buf, page_kib)
+}
+
+/// Returns all processes.
+pub fn scan() -> Vec<Process> {
+    let mut buf = String::with_capacity(512);
+    pids().into_iter().filter_map(|pid| read_process(pid, &mut buf)).collect()
+}
+
+/// Remembers the CPU time of processes in order to compute their usage until the next scan,
+/// like CPULoadMetric does for the whole system.
+pub struct CpuUsage {
+    last_ticks: HashMap<i32, u64>,
+    /// Time of the last update in epoch milliseconds; 0 before the first one.
+    last_time: i64,
+    millis_per_tick: f64,
+}
+
+impl CpuUsage {
+    pub fn new() -> CpuUsage {
+        CpuUsage {
+            last_ticks: HashMap::new(),
+            last_time: 0,
+            millis_per_tick: 1000. / unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64,
+        }
+    }
+
+    /// Returns the usage of each process since the last update, in percent of one CPU. All are 0
+    /// on the first update; processes that didn't exist before count from their start.
+    pub fn update(&mut self, procs: &[Process], now: i64) -> Vec<f64> {
+        let interval = (now - self.last_time) as f64;
+        let first = self.last_time == 0 || interval <= 0.;
+
+        let usage = procs.iter()
+                         .map(|p| {
+                             let last = self.last_ticks.get(&p.pid).cloned().unwrap_or(0);
+                             if first {
+                                 0.
+                             } else {
+                                 100. * p.ticks.saturating_sub(last) as f64 *
+                                 self.millis_per_tick / interval
+                             }
+                         })
+                         .collect();
+        // Rebuilt, so that exited processes are forgotten.
+        self.last_ticks = procs.iter().map(|p| (p.pid, p.ticks)).collect();
+        self.last_time = now;
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "4711 (Web Content (1)) S 1 4711 4711 0 -1 4194560 87123 0 12 0 1534 230 0 0 \
+                    20 0 31 0 82342 3092471808 51200 18446744073709551615 1 1 0 0 0 0 0 4096 \
+                    17663 0 0 0 17 3 0 0 0 0 0\n";
+        let p = parse_stat(4711, stat, 4).unwrap();
+        assert_eq!(p.name, "Web Content (1)");
+        assert_eq!(p.ticks, 1764);
+        assert_eq!(p.rss, 204800);
+        assert!(parse_stat(1, "1 (init) S 0", 4).is_none());
+    }
+
+    #[test]
+    fn test_cpu_usage() {
+        let process = |pid, ticks| {
+            Process {
+                pid: pid,
+                name: String::new(),
+                ticks: ticks,
+                rss: 0,
+            }
+        };
+        let mut usage = CpuUsage::new();
+        usage.millis_per_tick = 10.;
+
+        assert_eq!(usage.update(&[process(1, 500), process(2, 100)], 1000), vec![0., 0.]);
+        // 2 seconds later: 1 used 100 ticks = 1s, 3 is new and used 0.5s.
+        assert_eq!(usage.update(&[process(1, 600), process(3, 50)], 3000), vec![50., 25.]);
+        assert!(!usage.last_ticks.contains_key(&2));
+    }
+
+    #[test]
+    fn test_scan() {
+        let me = ::std::process::id() as i32;
+        assert!(scan().iter().any(|p| p.pid == me && p.rss > 0));
+    }
+}
@@ -1,8 +1,28 @@
 use framework::*;
+use json;
+use schedule::Scheduler;
+use signals;
+
+use std::io::{self, BufRead};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
 
 pub trait Renderer {
     fn init(&mut self, metrics: Vec<ActiveMetric>) -> String;
     fn render(&mut self) -> String;
+    /// Exchanges the set of rendered metrics without re-initializing the output; returns the
+    /// previous set.
+    fn replace_metrics(&mut self, metrics: Vec<ActiveMetric>) -> Vec<ActiveMetric>;
+}
+
+/// Source of configuration reloads, polled by render_loop().
+pub trait Reload {
+    /// Returns true if the configuration should be re-read.
+    fn pending(&mut self) -> bool;
+    /// Re-evaluates the configuration. The previously active metrics are passed in so that
+    /// unchanged ones can be carried over including their state. Returns the new set of metrics
+    /// and the new interval.
+    fn reload(&mut self, old: Vec<ActiveMetric>) -> (Vec<ActiveMetric>, i32);
 }
 
 struct I3statRenderer {
@@ -19,7 +39,10 @@ impl Renderer for I3statRenderer {
     fn init(&mut self, metrics: Vec<ActiveMetric>) -> String {
         self.metrics = metrics;
 
-        String::from("{\"version\":1}\n[[]\n")
+        format!("{{\"version\":1,\"stop_signal\":{},\"cont_signal\":{},\
+                 \"click_events\":true}}\n[[]\n",
+                signals::STOP_SIGNAL,
+                signals::CONT_SIGNAL)
     }
 
     fn render(&mut self) -> String {
@@ -34,6 +57,10 @@ impl Renderer for I3statRenderer {
         render_result.pop();
         format!(",[{}]", render_result)
     }
+
+    fn replace_metrics(&mut self, metrics: Vec<ActiveMetric>) -> Vec<ActiveMetric> {
+        ::std::mem::replace(&mut self.metrics, metrics)
+    }
 }
 
 pub fn make_i3status() -> Box<Renderer> {
@@ -73,28 +100,124 @@ impl Renderer for PlainTextRenderer {
         self.metrics.iter_mut().map(|m| m.render()).fold(String::from(""), |mut out, rendres| {
             let (txt, col) = rendres.get();
             out.push_str(&PlainTextRenderer::color_to_ansi(col));
+            if rendres.is_urgent() {
+                // reverse video
+                out.push_str("\x1b[7m");
+            }
             out.push_str(&txt);
             out.push_str(&PlainTextRenderer::color_to_ansi(Color::Default));
             out.push_str(" | ");
             out
         })
     }
+
+    fn replace_metrics(&mut self, metrics: Vec<ActiveMetric>) -> Vec<ActiveMetric> {
+        ::std::mem::replace(&mut self.metrics, metrics)
+    }
 }
 
 pub fn make_plaintextrenderer() -> Box<Renderer> {
     Box::new(PlainTextRenderer::new())
 }
 
-pub fn render_loop(mut r: Box<Renderer>, metrics: Vec<ActiveMetric>, interval: i32) {
+/// Calls f on every metric currently held by the renderer.
+fn for_each_metric<F: FnMut(&mut ActiveMetric)>(r: &mut Box<Renderer>, mut f: F) {
+    let mut metrics = r.replace_metrics(Vec::new());
+    for m in metrics.iter_mut() {
+        f(m);
+    }
+    r.replace_metrics(metrics);
+}
+
+/// Blocks until rendering is resumed, then lets all metrics take a new baseline.
+fn wait_for_continue(r: &mut Box<Renderer>) {
     use std::thread::sleep;
     use std::time::Duration;
 
-    let ival_duration = Duration::new((interval / 1000) as u64, 1000000 * (interval as u32 % 1000));
+    while signals::paused() {
+        sleep(Duration::from_millis(100));
+    }
+    for_each_metric(r, |m| m.reset());
+}
+
+fn parse_click_event(line: &str) -> Option<ClickEvent> {
+    let ev = match json::parse(line) {
+        Err(_) => return None,
+        Ok(ev) => ev,
+    };
+
+    ev.get("name").and_then(json::Json::as_str).map(|name| {
+        ClickEvent {
+            name: String::from(name),
+            instance: ev.get("instance").and_then(json::Json::as_str).map(String::from),
+            button: ev.get("button").and_then(json::Json::as_f64).unwrap_or(0.) as u32,
+        }
+    })
+}
+
+/// Reads the click events sent by i3bar on stdin (an endless JSON array, one event per line)
+/// and forwards them.
+///
+///     [
+///     {"name":"clock","button":1,"x":1320,"y":1400}
+///     ,{"name":"clock","button":3,"x":1320,"y":1400}
+fn read_click_events(events: Sender<Wakeup>) {
+    let stdin = io::stdin();
+
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Err(_) => return,
+            Ok(l) => l,
+        };
+        let line = line.trim().trim_start_matches(|c| c == '[' || c == ',');
+
+        if let Some(ev) = parse_click_event(line) {
+            if events.send(Wakeup::Click(ev)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn spawn_click_reader(events: Sender<Wakeup>) {
+    thread::spawn(move || read_click_events(events));
+}
+
+/// Renders the metrics until the process is terminated. Clicks are sent to wakeups and handled
+/// here; metrics hold notifiers sending to the same channel.
+pub fn render_loop(mut r: Box<Renderer>,
+                   metrics: Vec<ActiveMetric>,
+                   interval: i32,
+                   reloader: &mut Reload,
+                   wakeups: (Sender<Wakeup>, Receiver<Wakeup>)) {
+    let mut scheduler = Scheduler::new(interval);
+    let (tx, events) = wakeups;
+    spawn_click_reader(tx);
 
     print!("{}", r.init(metrics));
 
     loop {
+        if signals::paused() {
+            wait_for_continue(&mut r);
+            scheduler.wait(&events);
+            continue;
+        }
+        if reloader.pending() {
+            let old = r.replace_metrics(Vec::new());
+            let (new, interval) = reloader.reload(old);
+            r.replace_metrics(new);
+            scheduler.set_interval(interval);
+        }
         println!("{}", r.render());
-        sleep(ival_duration);
+
+        // Clicks usually change what a metric shows, so render again right away; the same goes
+        // for updates pushed by metrics.
+        for wakeup in scheduler.wait(&events) {
+            if let Wakeup::Click(ev) = wakeup {
+                for_each_metric(&mut r, |m| if *m.name() == ev.name {
+                    m.click(&ev)
+                });
+            }
+        }
     }
 }
@@ -0,0 +1,103 @@
+//! Determines when the next render cycle should happen.
+
+extern crate chrono;
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::sleep;
+use std::time::Duration;
+
+use framework::MetricState;
+
+/// Schedules render cycles on wall-clock boundaries of the interval, e.g. on every full second
+/// for an interval of 1000 ms or on every full minute for 60000 ms (in local time). The time
+/// spent rendering is compensated for, and missed deadlines (after a suspend or a clock jump) are
+/// skipped instead of being caught up with. Waiting is interrupted by incoming events, e.g.
+/// clicks.
+pub struct Scheduler {
+    /// Interval in milliseconds.
+    interval: i64,
+}
+
+impl Scheduler {
+    pub fn new(interval: i32) -> Scheduler {
+        let mut s = Scheduler { interval: 1 };
+        s.set_interval(interval);
+        s
+    }
+
+    pub fn set_interval(&mut self, interval: i32) {
+        self.interval = if interval > 0 { interval as i64 } else { 1 };
+    }
+
+    /// Offset of local time to UTC in milliseconds.
+    fn utc_offset() -> i64 {
+        use self::chrono::Offset;
+        chrono::Local::now().offset().local_minus_utc().num_milliseconds()
+    }
+
+    /// Returns the first deadline (in epoch milliseconds) after now that is a multiple of the
+    /// interval in local time.
+    fn next_deadline(&self, now: i64, utc_offset: i64) -> i64 {
+        let local = now + utc_offset;
+        let next = (local.div_euclid(self.interval) + 1) * self.interval;
+        next - utc_offset
+    }
+
+    /// Waits until the next deadline, or until events arrive. Returns the events that arrived.
+    pub fn wait<T>(&self, events: &Receiver<T>) -> Vec<T> {
+        let now = MetricState::now();
+        let deadline = self.next_deadline(now, Scheduler::utc_offset());
+        let mut received = Scheduler::sleep(events, deadline - now);
+
+        // The sleep is measured on a monotonic clock, so the wall clock may lag slightly behind
+        // and we'd render just before the boundary. If it is far behind, the clock was set back;
+        // then don't wait any longer and re-align in the next cycle.
+        let now = MetricState::now();
+        if received.is_empty() && now < deadline && deadline - now < self.interval {
+            received = Scheduler::sleep(events, deadline - now);
+        }
+        received
+    }
+
+    /// Sleeps for millis milliseconds, or until events arrive.
+    fn sleep<T>(events: &Receiver<T>, millis: i64) -> Vec<T> {
+        let timeout = Duration::from_millis(millis as u64);
+
+        match events.recv_timeout(timeout) {
+            Ok(ev) => {
+                let mut received = vec![ev];
+                received.extend(events.try_iter());
+                received
+            }
+            Err(RecvTimeoutError::Timeout) => Vec::new(),
+            Err(RecvTimeoutError::Disconnected) => {
+                sleep(timeout);
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scheduler;
+
+    #[test]
+    fn test_next_deadline() {
+        let s = Scheduler::new(1000);
+        assert_eq!(s.next_deadline(1500, 0), 2000);
+        assert_eq!(s.next_deadline(2000, 0), 3000);
+        assert_eq!(s.next_deadline(2999, 0), 3000);
+
+        let s = Scheduler::new(60000);
+        assert_eq!(s.next_deadline(61000, 0), 120000);
+        // UTC+05:30
+        assert_eq!(s.next_deadline(61000, 19800000), 120000);
+        // UTC+05:45
+        assert_eq!(s.next_deadline(61000, 20700000), 120000);
+
+        // hourly interval in UTC+05:30 is aligned to local full hours.
+        let s = Scheduler::new(3600000);
+        assert_eq!(s.next_deadline(0, 19800000), 1800000);
+    }
+}
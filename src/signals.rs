@@ -0,0 +1,50 @@
+//! Signal handling. Handlers only set flags which are polled by the render loop.
+
+#![allow(dead_code)]
+
+extern crate libc;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Signal sent by i3bar when the bar is hidden and updates should be paused.
+pub const STOP_SIGNAL: libc::c_int = libc::SIGUSR1;
+/// Signal sent by i3bar when updates should be resumed.
+pub const CONT_SIGNAL: libc::c_int = libc::SIGUSR2;
+
+static RELOAD: AtomicBool = AtomicBool::new(false);
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    RELOAD.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_stop(_: libc::c_int) {
+    PAUSED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_cont(_: libc::c_int) {
+    PAUSED.store(false, Ordering::SeqCst);
+}
+
+fn set_handler(sig: libc::c_int, handler: extern "C" fn(libc::c_int)) {
+    unsafe {
+        libc::signal(sig, handler as libc::sighandler_t);
+    }
+}
+
+/// Installs the signal handlers used by myi3stat.
+pub fn install_handlers() {
+    set_handler(libc::SIGHUP, on_sighup);
+    set_handler(STOP_SIGNAL, on_stop);
+    set_handler(CONT_SIGNAL, on_cont);
+}
+
+/// Returns true if a configuration reload was requested (by SIGHUP) since the last call.
+pub fn reload_requested() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+/// Returns true while i3bar asked us to stop updating.
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,652 @@
+//! Time zones from the tz database (see tzfile(5)), and locale-aware time formatting.
+
+#![allow(dead_code)]
+
+extern crate chrono;
+extern crate libc;
+
+use self::chrono::{Datelike, FixedOffset, TimeZone as ChronoTimeZone};
+
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::Read;
+
+const DEFAULT_TZDIR: &'static str = "/usr/share/zoneinfo";
+
+/// Offset, DST flag and abbreviation of a zone at some point in time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalTimeType {
+    /// Offset from UTC in seconds (east is positive).
+    pub offset: i32,
+    pub is_dst: bool,
+    pub abbr: String,
+}
+
+/// A day of the year on which DST starts or ends, as specified in a POSIX TZ string.
+#[derive(Clone, Debug, PartialEq)]
+enum RuleDay {
+    /// Jn: day 1..365, February 29 is never counted.
+    Julian1(i64),
+    /// n: day 0..365, counting February 29 in leap years.
+    Julian0(i64),
+    /// Mm.w.d: day d (0 = Sunday) of week w (5 = last) of month m.
+    MonthWeekDay(i64, i64, i64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct DstRule {
+    dst: LocalTimeType,
+    start: RuleDay,
+    /// Seconds after local midnight at which the change happens.
+    start_time: i64,
+    end: RuleDay,
+    end_time: i64,
+}
+
+/// A POSIX TZ string like "CET-1CEST,M3.5.0,M10.5.0/3".
+#[derive(Clone, Debug, PartialEq)]
+struct PosixRule {
+    std: LocalTimeType,
+    dst: Option<DstRule>,
+}
+
+#[derive(Clone)]
+pub struct TimeZone {
+    /// Transition times in epoch seconds, with the index of the local time type starting then.
+    transitions: Vec<(i64, usize)>,
+    types: Vec<LocalTimeType>,
+    /// Applies after the last transition.
+    rule: Option<PosixRule>,
+}
+
+/// Days since 1970-01-01 of the given date (proleptic Gregorian calendar).
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Year of the given day since 1970-01-01.
+fn year_from_days(days: i64) -> i64 {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let y = yoe + era * 400;
+    if mp >= 10 { y + 1 } else { y }
+}
+
+fn is_leap(y: i64) -> bool {
+    y % 4 == 0 && (y % 100 != 0 || y % 400 == 0)
+}
+
+impl RuleDay {
+    /// Returns the day (since 1970-01-01) this rule denotes in year y.
+    fn in_year(&self, y: i64) -> i64 {
+        let jan1 = days_from_civil(y, 1, 1);
+
+        match *self {
+            RuleDay::Julian1(n) => jan1 + n - 1 + if is_leap(y) && n >= 60 { 1 } else { 0 },
+            RuleDay::Julian0(n) => jan1 + n,
+            RuleDay::MonthWeekDay(m, w, d) => {
+                let first = days_from_civil(y, m, 1);
+                let next_month = if m == 12 {
+                    days_from_civil(y + 1, 1, 1)
+                } else {
+                    days_from_civil(y, m + 1, 1)
+                };
+                // 1970-01-01 was a Thursday.
+                let first_wday = (first + 4).rem_euclid(7);
+                let mut day = first + (d - first_wday).rem_euclid(7) + (w - 1) * 7;
+                while day >= next_month {
+                    day -= 7;
+                }
+                day
+            }
+        }
+    }
+}
+
+impl PosixRule {
+    fn at(&self, ts: i64) -> &LocalTimeType {
+        let rule = match self.dst {
+            None => return &self.std,
+            Some(ref r) => r,
+        };
+
+        let year = year_from_days((ts + self.std.offset as i64).div_euclid(86400));
+        let start = rule.start.in_year(year) * 86400 + rule.start_time - self.std.offset as i64;
+        let end = rule.end.in_year(year) * 86400 + rule.end_time - rule.dst.offset as i64;
+
+        let in_dst = if start < end {
+            start <= ts && ts < end
+        } else {
+            // southern hemisphere
+            !(end <= ts && ts < start)
+        };
+        if in_dst { &rule.dst } else { &self.std }
+    }
+
+    fn parse(s: &str) -> Option<PosixRule> {
+        let mut p = s;
+
+        let std_abbr = PosixRule::parse_abbr(&mut p)?;
+        let std_offset = -PosixRule::parse_time(&mut p)?;
+        let std = LocalTimeType {
+            offset: std_offset as i32,
+            is_dst: false,
+            abbr: std_abbr,
+        };
+        if p.is_empty() {
+            return Some(PosixRule {
+                std: std,
+                dst: None,
+            });
+        }
+
+        let dst_abbr = PosixRule::parse_abbr(&mut p)?;
+        let dst_offset = if p.is_empty() || p.starts_with(",") {
+            std_offset + 3600
+        } else {
+            -PosixRule::parse_time(&mut p)?
+        };
+        // Without explicit rules, use the current US ones.
+        if p.is_empty() {
+            p = ",M3.2.0,M11.1.0";
+        }
+
+        let (start, start_time) = PosixRule::parse_rule(&mut p)?;
+        let (end, end_time) = PosixRule::parse_rule(&mut p)?;
+
+        Some(PosixRule {
+            std: std,
+            dst: Some(DstRule {
+                dst: LocalTimeType {
+                    offset: dst_offset as i32,
+                    is_dst: true,
+                    abbr: dst_abbr,
+                },
+                start: start,
+                start_time: start_time,
+                end: end,
+                end_time: end_time,
+            }),
+        })
+    }
+
+    /// Parses a zone abbreviation: alphabetic, or quoted in <>.
+    fn parse_abbr(p: &mut &str) -> Option<String> {
+        let (abbr, rest) = if p.starts_with("<") {
+            let end = p.find('>')?;
+            (&p[1..end], &p[end + 1..])
+        } else {
+            let end = p.find(|c: char| !c.is_alphabetic()).unwrap_or(p.len());
+            (&p[..end], &p[end..])
+        };
+        if abbr.is_empty() {
+            return None;
+        }
+        *p = rest;
+        Some(String::from(abbr))
+    }
+
+    /// Parses [+-]hh[:mm[:ss]] into seconds.
+    fn parse_time(p: &mut &str) -> Option<i64> {
+        let end = p.find(|c: char| !(c.is_digit(10) || c == ':' || c == '+' || c == '-'))
+                   .unwrap_or(p.len());
+        let (time, rest) = (&p[..end], &p[end..]);
+        let (sign, time) = if time.starts_with("-") {
+            (-1, &time[1..])
+        } else if time.starts_with("+") {
+            (1, &time[1..])
+        } else {
+            (1, time)
+        };
+
+        let mut secs = 0;
+        let mut factor = 3600;
+        for part in time.split(':') {
+            secs += factor * part.parse::<i64>().ok()?;
+            factor /= 60;
+        }
+        *p = rest;
+        Some(sign * secs)
+    }
+
+    /// Parses ",date[/time]".
+    fn parse_rule(p: &mut &str) -> Option<(RuleDay, i64)> {
+        if !p.starts_with(",") {
+            return None;
+        }
+        *p = &p[1..];
+
+        let end = p.find(|c| c == ',' || c == '/').unwrap_or(p.len());
+        let date = &p[..end];
+        *p = &p[end..];
+
+        let day = if date.starts_with("M") {
+            let parts: Vec<i64> = date[1..].split('.').filter_map(|n| n.parse().ok()).collect();
+            if parts.len() != 3 {
+                return None;
+            }
+            RuleDay::MonthWeekDay(parts[0], parts[1], parts[2])
+        } else if date.starts_with("J") {
+            RuleDay::Julian1(date[1..].parse().ok()?)
+        } else {
+            RuleDay::Julian0(date.parse().ok()?)
+        };
+
+        let time = if p.starts_with("/") {
+            *p = &p[1..];
+            PosixRule::parse_time(p)?
+        } else {
+            7200
+        };
+        Some((day, time))
+    }
+}
+
+fn be_i32(b: &[u8]) -> i32 {
+    ((b[0] as i32) << 24) | ((b[1] as i32) << 16) | ((b[2] as i32) << 8) | b[3] as i32
+}
+
+fn be_i64(b: &[u8]) -> i64 {
+    ((be_i32(&b[0..4]) as i64) << 32) | (be_i32(&b[4..8]) as u32 as i64)
+}
+
+impl TimeZone {
+    pub fn utc() -> TimeZone {
+        TimeZone {
+            transitions: Vec::new(),
+            types: Vec::new(),
+            rule: Some(PosixRule {
+                std: LocalTimeType {
+                    offset: 0,
+                    is_dst: false,
+                    abbr: String::from("UTC"),
+                },
+                dst: None,
+            }),
+        }
+    }
+
+    /// Loads a zone by its IANA name (e.g. "America/New_York") from $TZDIR or
+    /// /usr/share/zoneinfo, or from an absolute path.
+    pub fn load(name: &str) -> Result<TimeZone, String> {
+        let path = if name.starts_with("/") {
+            String::from(name)
+        } else if name.split('/').any(|c| c == "..") {
+            return Err(format!("invalid time zone '{}'", name));
+        } else {
+            let dir = env::var("TZDIR").unwrap_or(String::from(DEFAULT_TZDIR));
+            format!("{}/{}", dir, name)
+        };
+
+        let mut data = Vec::new();
+        if let Err(e) = fs::File::open(&path).and_then(|mut f| f.read_to_end(&mut data)) {
+            return Err(format!("unknown time zone '{}' ({})", name, e));
+        }
+        TimeZone::parse(&data).ok_or(format!("{}: not a valid tzfile", path))
+    }
+
+    /// Returns the local zone as configured by $TZ or /etc/localtime; falls back to UTC.
+    pub fn local() -> TimeZone {
+        if let Ok(tz) = env::var("TZ") {
+            let tz = tz.trim_start_matches(':');
+            if !tz.is_empty() {
+                if let Ok(zone) = TimeZone::load(tz) {
+                    return zone;
+                }
+                if let Some(rule) = PosixRule::parse(tz) {
+                    return TimeZone {
+                        transitions: Vec::new(),
+                        types: Vec::new(),
+                        rule: Some(rule),
+                    };
+                }
+            }
+        }
+        TimeZone::load("/etc/localtime").unwrap_or(TimeZone::utc())
+    }
+
+    /// Parses the contents of a tzfile.
+    pub fn parse(data: &[u8]) -> Option<TimeZone> {
+        if data.len() < 44 || &data[0..4] != b"TZif" {
+            return None;
+        }
+        let version = data[4];
+        let (zone, end) = TimeZone::parse_block(data, 0, 4)?;
+        if version < b'2' {
+            return Some(zone);
+        }
+
+        // Version 2+ files repeat the data with 64 bit times, followed by a POSIX TZ string.
+        let (mut zone, end) = TimeZone::parse_block(data, end, 8)?;
+        let footer = &data[end..];
+        if footer.len() > 2 && footer[0] == b'\n' {
+            let tz_end = footer[1..].iter().position(|&b| b == b'\n').unwrap_or(footer.len() - 1);
+            zone.rule = ::std::str::from_utf8(&footer[1..1 + tz_end])
+                            .ok()
+                            .and_then(PosixRule::parse);
+        }
+        Some(zone)
+    }
+
+    /// Parses header and data block starting at off; returns the zone and the offset after the
+    /// block. None if the data is malformed or truncated.
+    fn parse_block(data: &[u8], off: usize, time_size: usize) -> Option<(TimeZone, usize)> {
+        if data.len() < off + 44 || &data[off..off + 4] != b"TZif" {
+            return None;
+        }
+        let count = |i: usize| {
+            let n = be_i32(&data[off + 20 + 4 * i..]);
+            if n < 0 { None } else { Some(n as usize) }
+        };
+        let (isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt) =
+            (count(0)?, count(1)?, count(2)?, count(3)?, count(4)?, count(5)?);
+
+        // The counts are up to 2^31 each, so the offsets may overflow on 32 bit systems.
+        let times = off + 44;
+        let indices = times.checked_add(timecnt.checked_mul(time_size)?)?;
+        let types = indices.checked_add(timecnt)?;
+        let chars = types.checked_add(typecnt.checked_mul(6)?)?;
+        let end = chars.checked_add(charcnt)?
+                       .checked_add(leapcnt.checked_mul(time_size + 4)?)?
+                       .checked_add(isstdcnt)?
+                       .checked_add(isutcnt)?;
+        if data.len() < end {
+            return None;
+        }
+
+        let mut zone = TimeZone {
+            transitions: Vec::with_capacity(timecnt),
+            types: Vec::with_capacity(typecnt),
+            rule: None,
+        };
+        for i in 0..timecnt {
+            let t = &data[times + i * time_size..];
+            let t = if time_size == 8 { be_i64(t) } else { be_i32(t) as i64 };
+            let idx = data[indices + i] as usize;
+            if idx >= typecnt {
+                return None;
+            }
+            zone.transitions.push((t, idx));
+        }
+        for i in 0..typecnt {
+            let tt = &data[types + i * 6..];
+            if tt[5] as usize >= charcnt {
+                return None;
+            }
+            let abbr_start = chars + tt[5] as usize;
+            let abbr_len = data[abbr_start..chars + charcnt]
+                               .iter()
+                               .position(|&b| b == 0)
+                               .unwrap_or(0);
+            zone.types.push(LocalTimeType {
+                offset: be_i32(tt),
+                is_dst: tt[4] != 0,
+                abbr: String::from_utf8_lossy(&data[abbr_start..abbr_start + abbr_len])
+                          .into_owned(),
+            });
+        }
+        Some((zone, end))
+    }
+
+    /// Returns offset and abbreviation in effect at ts (epoch seconds).
+    pub fn local_time_type(&self, ts: i64) -> LocalTimeType {
+        let idx = match self.transitions.binary_search_by(|&(t, _)| t.cmp(&ts)) {
+            Ok(i) => Some(i),
+            Err(0) => None,
+            Err(i) => Some(i - 1),
+        };
+
+        match (idx, &self.rule) {
+            (Some(i), &Some(ref rule)) if i == self.transitions.len() - 1 => rule.at(ts).clone(),
+            (Some(i), _) => self.types[self.transitions[i].1].clone(),
+            (None, &Some(ref rule)) if self.transitions.is_empty() => rule.at(ts).clone(),
+            (None, _) => {
+                // Before the first transition, the first standard time type applies.
+                self.types
+                    .iter()
+                    .find(|t| !t.is_dst)
+                    .or(self.types.first())
+                    .cloned()
+                    .unwrap_or(TimeZone::utc().local_time_type(0))
+            }
+        }
+    }
+
+    /// Converts a local wall-clock time (seconds since the epoch as if the zone were UTC) to epoch
+    /// seconds.
+    pub fn utc_from_local(&self, local: i64) -> i64 {
+        // The offset depends on the instant we're looking for; refining once more gets
+        // transitions right.
+        let ts = local - self.local_time_type(local).offset as i64;
+        local - self.local_time_type(ts).offset as i64
+    }
+}
+
+const LC_TIME: libc::c_int = 2;
+
+/// struct tm as defined by glibc.
+#[repr(C)]
+struct Tm {
+    tm_sec: libc::c_int,
+    tm_min: libc::c_int,
+    tm_hour: libc::c_int,
+    tm_mday: libc::c_int,
+    tm_mon: libc::c_int,
+    tm_year: libc::c_int,
+    tm_wday: libc::c_int,
+    tm_yday: libc::c_int,
+    tm_isdst: libc::c_int,
+    tm_gmtoff: libc::c_long,
+    tm_zone: *const libc::c_char,
+}
+
+extern "C" {
+    fn setlocale(category: libc::c_int, locale: *const libc::c_char) -> *mut libc::c_char;
+    fn strftime(s: *mut libc::c_char,
+                max: libc::size_t,
+                format: *const libc::c_char,
+                tm: *const Tm)
+                -> libc::size_t;
+}
+
+/// Names of days and months in the locale selected by LC_ALL/LC_TIME/LANG.
+pub struct LocaleNames {
+    days_abbr: Vec<String>,
+    days: Vec<String>,
+    months_abbr: Vec<String>,
+    months: Vec<String>,
+}
+
+impl LocaleNames {
+    pub fn from_env() -> LocaleNames {
+        unsafe {
+            setlocale(LC_TIME, CString::new("").unwrap().as_ptr());
+        }
+        let day = |fmt: &str, i| LocaleNames::strftime_tm(fmt, i, 0);
+        let month = |fmt: &str, i| LocaleNames::strftime_tm(fmt, 0, i);
+
+        LocaleNames {
+            days_abbr: (0..7).map(|i| day("%a", i)).collect(),
+            days: (0..7).map(|i| day("%A", i)).collect(),
+            months_abbr: (0..12).map(|i| month("%b", i)).collect(),
+            months: (0..12).map(|i| month("%B", i)).collect(),
+        }
+    }
+
+    /// Formats a struct tm with only weekday and month set.
+    fn strftime_tm(fmt: &str, wday: i32, mon: i32) -> String {
+        let tm = Tm {
+            tm_sec: 0,
+            tm_min: 0,
+            tm_hour: 0,
+            tm_mday: 1,
+            tm_mon: mon,
+            tm_year: 100,
+            tm_wday: wday,
+            tm_yday: 0,
+            tm_isdst: 0,
+            tm_gmtoff: 0,
+            tm_zone: ::std::ptr::null(),
+        };
+        let mut buf = [0 as libc::c_char; 64];
+        let cfmt = CString::new(fmt).unwrap();
+
+        unsafe {
+            let n = strftime(buf.as_mut_ptr(), buf.len() as libc::size_t, cfmt.as_ptr(), &tm);
+            if n == 0 {
+                return String::new();
+            }
+            CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+        }
+    }
+}
+
+/// Formats the instant ts (epoch seconds) in zone. fmt uses the strftime-like syntax of
+/// chrono; day and month names (%a, %A, %b, %h, %B) follow the locale and %Z is replaced by the
+/// zone abbreviation. fmt must have been checked by helper::check_strftime().
+pub fn format_time(ts: i64, zone: &TimeZone, names: &LocaleNames, fmt: &str) -> String {
+    let lt = zone.local_time_type(ts);
+    let dt = FixedOffset::east(lt.offset).timestamp(ts, 0);
+    let wday = dt.weekday().num_days_from_sunday() as usize;
+    let mon = dt.month0() as usize;
+
+    // Substitute the specifiers chrono doesn't handle the way we want by literal text.
+    let mut chrono_fmt = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            chrono_fmt.push(c);
+            continue;
+        }
+        let spec = match chars.next() {
+            None => break,
+            Some(s) => s,
+        };
+        let text = match spec {
+            'a' => &names.days_abbr[wday],
+            'A' => &names.days[wday],
+            'b' | 'h' => &names.months_abbr[mon],
+            'B' => &names.months[mon],
+            'Z' => &lt.abbr,
+            _ => {
+                chrono_fmt.push('%');
+                chrono_fmt.push(spec);
+                continue;
+            }
+        };
+        chrono_fmt.push_str(&text.replace("%", "%%"));
+    }
+
+    format!("{}", dt.format(&chrono_fmt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2026-01-15 12:00:00 UTC, 2026-07-01 12:00:00 UTC
+    const WINTER: i64 = 1768478400;
+    const SUMMER: i64 = 1782907200;
+
+    #[test]
+    fn test_posix_rule() {
+        let cet = PosixRule::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        assert_eq!(cet.at(WINTER).offset, 3600);
+        assert_eq!(cet.at(SUMMER).abbr, "CEST");
+        assert_eq!(cet.at(SUMMER).offset, 7200);
+        // DST starts 2026-03-29 01:00 UTC
+        assert_eq!(cet.at(1774746000 - 1).offset, 3600);
+        assert_eq!(cet.at(1774746000).offset, 7200);
+
+        // southern hemisphere
+        let aus = PosixRule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(aus.at(WINTER).abbr, "AEDT");
+        assert_eq!(aus.at(SUMMER).abbr, "AEST");
+
+        let ir = PosixRule::parse("<+0330>-3:30").unwrap();
+        assert_eq!(ir.at(SUMMER).offset, 12600);
+        assert_eq!(ir.at(SUMMER).abbr, "+0330");
+
+        assert_eq!(PosixRule::parse(""), None);
+    }
+
+    /// Version 1 file with one transition (at WINTER) from "AAA" (+1h) to "BBB" (+2h, DST).
+    fn tzfile() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"TZif\0");
+        data.extend_from_slice(&[0; 15]);
+        for count in &[0u32, 0, 0, 1, 2, 8] {
+            data.extend_from_slice(&[0, 0, 0, *count as u8]);
+        }
+        data.extend_from_slice(&[(WINTER >> 24) as u8,
+                                 (WINTER >> 16) as u8,
+                                 (WINTER >> 8) as u8,
+                                 WINTER as u8]);
+        data.push(1);
+        data.extend_from_slice(&[0, 0, 0x0e, 0x10, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0x1c, 0x20, 1, 4]);
+        data.extend_from_slice(b"AAA\0BBB\0");
+        data
+    }
+
+    #[test]
+    fn test_parse_tzfile() {
+        let data = tzfile();
+        let zone = TimeZone::parse(&data).unwrap();
+        assert_eq!(zone.local_time_type(WINTER - 1).abbr, "AAA");
+        assert_eq!(zone.local_time_type(WINTER).offset, 7200);
+        assert!(zone.local_time_type(SUMMER).is_dst);
+        assert!(TimeZone::parse(b"nonsense").is_none());
+    }
+
+    #[test]
+    fn test_parse_corrupted_tzfile() {
+        // Abbreviation index past the abbreviations.
+        let mut data = tzfile();
+        let len = data.len();
+        data[len - 9] = 9;
+        assert!(TimeZone::parse(&data).is_none());
+
+        // Negative and huge counts.
+        let mut data = tzfile();
+        data[32..36].copy_from_slice(&[0xff, 0xff, 0xff, 0xf0]);
+        assert!(TimeZone::parse(&data).is_none());
+        let mut data = tzfile();
+        data[32..36].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff]);
+        assert!(TimeZone::parse(&data).is_none());
+
+        let data = tzfile();
+        assert!(TimeZone::parse(&data[..data.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_format_time() {
+        let names = LocaleNames {
+            days_abbr: vec!["So", "Mo", "Di", "Mi", "Do", "Fr", "Sa"]
+                           .into_iter()
+                           .map(String::from)
+                           .collect(),
+            days: vec![String::new(); 7],
+            months_abbr: vec![String::from("%M"); 12],
+            months: vec![String::new(); 12],
+        };
+        let zone = TimeZone {
+            transitions: Vec::new(),
+            types: Vec::new(),
+            rule: PosixRule::parse("CET-1CEST,M3.5.0,M10.5.0/3"),
+        };
+
+        assert_eq!(format_time(SUMMER, &zone, &names, "%a %H:%M %Z %b %%a"),
+                   "Mi 14:00 CEST %M %a");
+    }
+}
@@ -0,0 +1,209 @@
+//! Background threads waking up the render loop when an event source of a metric fires, so that
+//! event-driven metrics are rendered right away instead of at the next interval boundary, or when
+//! a query that takes a while has finished.
+
+extern crate libc;
+
+use framework::Notifier;
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// How often watching threads check whether their metric is still alive, in milliseconds.
+const CHECK_INTERVAL: i32 = 1000;
+
+/// Shared between a metric's state and the thread watching one of its sources. The thread only
+/// waits for the source while armed: a readable descriptor stays readable until the metric
+/// consumes the data in its next render, after which the framework re-arms.
+pub struct Arm {
+    armed: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Arm {
+    pub fn new() -> Arc<Arm> {
+        Arc::new(Arm {
+            armed: Mutex::new(true),
+            cond: Condvar::new(),
+        })
+    }
+
+    pub fn rearm(&self) {
+        *self.armed.lock().unwrap() = true;
+        self.cond.notify_all();
+    }
+
+    fn disarm(&self) {
+        *self.armed.lock().unwrap() = false;
+    }
+
+    /// Waits until armed, at most for CHECK_INTERVAL; returns whether armed.
+    fn wait(&self) -> bool {
+        let armed = self.armed.lock().unwrap();
+        if *armed {
+            return true;
+        }
+        let timeout = Duration::from_millis(CHECK_INTERVAL as u64);
+        *self.cond.wait_timeout(armed, timeout).unwrap().0
+    }
+}
+
+/// Notifies whenever fd becomes readable (or hung up) while arm is armed. The thread ends once
+/// the other references to arm are gone, i.e. the metric was dropped, or fd was closed.
+pub fn watch_fd(fd: i32, arm: Arc<Arm>, notifier: Notifier) {
+    thread::spawn(move || {
+        while Arc::strong_count(&arm) > 1 {
+            if !arm.wait() {
+                continue;
+            }
+            let mut pfd = libc::pollfd {
+                fd: fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut pfd, 1, CHECK_INTERVAL) } <= 0 {
+                continue;
+            }
+            if pfd.revents & libc::POLLNVAL != 0 {
+                return;
+            }
+            arm.disarm();
+            if !notifier.notify() {
+                return;
+            }
+        }
+    });
+}
+
+/// Notifies once at the given time (epoch milliseconds).
+pub fn wake_at(when: i64, now: i64, notifier: Notifier) {
+    thread::spawn(move || {
+        if when > now {
+            thread::sleep(Duration::from_millis((when - now) as u64));
+        }
+        notifier.notify();
+    });
+}
+
+/// Runs a query on a thread of its own every interval milliseconds, or right away when asked to,
+/// and keeps the latest result, so that e.g. running a process that hangs doesn't block the bar.
+/// Notifies after each query; the thread ends once the Poller has been dropped.
+pub struct Poller<T> {
+    result: Arc<Mutex<Option<T>>>,
+    refresh: Sender<()>,
+}
+
+impl<T: Clone + Send + 'static> Poller<T> {
+    pub fn start<F>(query: F, interval: i64, notifier: Option<Notifier>) -> Poller<T>
+        where F: Fn() -> T + Send + 'static
+    {
+        let result = Arc::new(Mutex::new(None));
+        let (refresh, requests) = channel();
+        let thread_result = result.clone();
+        thread::spawn(move || {
+            loop {
+                let r = query();
+                *thread_result.lock().unwrap() = Some(r);
+                if let Some(ref n) = notifier {
+                    n.notify();
+                }
+                let timeout = Duration::from_millis(interval as u64);
+                if let Err(RecvTimeoutError::Disconnected) = requests.recv_timeout(timeout) {
+                    return;
+                }
+                // Several requests during one query are answered by the next one.
+                while requests.try_recv().is_ok() {}
+            }
+        });
+        Poller {
+            result: result,
+            refresh: refresh,
+        }
+    }
+
+    /// Returns the latest result; None until the first query has finished.
+    pub fn get(&self) -> Option<T> {
+        self.result.lock().unwrap().clone()
+    }
+
+    /// Queries again without waiting for the interval to pass.
+    pub fn refresh(&self) {
+        let _ = self.refresh.send(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate libc;
+
+    use super::*;
+    use framework::{MetricState, Wakeup};
+
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+
+    #[test]
+    fn test_watch_fd() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let write = |fd| unsafe { libc::write(fd, b"x".as_ptr() as *const libc::c_void, 1) };
+
+        let (tx, rx) = channel();
+        let notifier = MetricState::with_wakeups(tx).notifier().unwrap();
+        let arm = Arm::new();
+        watch_fd(fds[0], arm.clone(), notifier);
+
+        let timeout = Duration::from_millis(2000);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        write(fds[1]);
+        assert!(match rx.recv_timeout(timeout) {
+            Ok(Wakeup::Update) => true,
+            _ => false,
+        });
+
+        // The data hasn't been read, but there is no new wakeup until re-armed.
+        assert_eq!(rx.recv_timeout(Duration::from_millis(200)).err(),
+                   Some(RecvTimeoutError::Timeout));
+        arm.rearm();
+        assert!(rx.recv_timeout(timeout).is_ok());
+
+        drop(arm);
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[test]
+    fn test_poller() {
+        let (tx, rx) = channel();
+        let notifier = MetricState::with_wakeups(tx).notifier();
+        let count = Arc::new(Mutex::new(0));
+        let thread_count = count.clone();
+        let poller = Poller::start(move || {
+                                       let mut c = thread_count.lock().unwrap();
+                                       *c += 1;
+                                       *c
+                                   },
+                                   60000,
+                                   notifier);
+
+        let timeout = Duration::from_millis(2000);
+        assert!(rx.recv_timeout(timeout).is_ok());
+        assert_eq!(poller.get(), Some(1));
+        poller.refresh();
+        assert!(rx.recv_timeout(timeout).is_ok());
+        assert_eq!(poller.get(), Some(2));
+
+        // The thread ends, dropping its reference.
+        drop(poller);
+        for _ in 0..20 {
+            if Arc::strong_count(&count) == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(Arc::strong_count(&count), 1);
+    }
+}